@@ -0,0 +1,26 @@
+//! Run with `cargo +nightly fuzz run escape_bytes` from the repository root.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_ansi::ansi_escaper::{escape_bytes, AnsiType, ToAnsi};
+
+fuzz_target!(|data: &[u8]| {
+    // Every call either makes progress or waits for more input
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (ansi, len) = escape_bytes(rest);
+        if ansi == AnsiType::Incomplete {
+            assert_eq!(len, 0);
+            break;
+        }
+        assert!(len > 0 && len <= rest.len(), "{:?} {:?} {}", rest, ansi, len);
+        rest = &rest[len..];
+    }
+
+    // Once a string ends with ST and a character, the incremental parser has nothing left over
+    if let Ok(s) = core::str::from_utf8(data) {
+        let mut escaper = format!("{}\x1B\\z", s).to_ansi();
+        escaper.parse_all();
+        assert_eq!(escaper.pending_bytes(), 0);
+    }
+});
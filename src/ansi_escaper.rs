@@ -3,14 +3,21 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::vec;
 use core::fmt::{Display, Error, Formatter};
+use core::iter::Peekable;
 use core::ops::Range;
-use std::println;
 use unicode_segmentation::UnicodeSegmentation;
+#[cfg(feature = "unicode-width")]
+use unicode_width::UnicodeWidthStr;
+use crate::sgr;
+use crate::sgr::SgrParams;
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AnsiType {
     /// Normal text
     Text(String),
+    /// C0 control character found in the text
+    Control(ControlChar),
 
     /// Single Shift 2
     SS2,
@@ -26,6 +33,16 @@ pub enum AnsiType {
     OSC {kind: OSCType},
     /// Reset to Initial State
     RIS,
+    /// IND, moves the cursor down a line, scrolling up at the bottom margin
+    Index,
+    /// RI, moves the cursor up a line, scrolling down at the top margin
+    ReverseIndex,
+    /// NEL, moves the cursor to the start of the next line, scrolling up at the bottom margin
+    NextLine,
+    /// DECKPAM, application keypad mode
+    KeypadApplication,
+    /// DECKPNM, normal (numeric) keypad mode
+    KeypadNumeric,
 
     // These three can be ignored (after parsing), as they are usually application specific
     /// Start of String
@@ -35,14 +52,60 @@ pub enum AnsiType {
     /// Application Program Command
     APC,
 
-    /// Used to set character sets on the original VT100, can mostly be ignored now
-    // TODO: Can it be ignored? Works fine on all apps I’ve tried, but some people probably want this code to work
+    /// Introducer of a character set designation, parsed sequences come out as `DesignateCharset`
     SETCHARSET,
+    /// Selects the character set used by one of the four slots, e.g. `ESC ( 0` for line drawing
+    DesignateCharset { slot: CharsetSlot, charset: Charset },
+    /// DECALN (`ESC # 8`), fills the screen with `E` for checking the alignment
+    DecAlignmentTest,
+    /// Double height / double width line controls (`ESC # 3` - `ESC # 6`)
+    DecLineAttr(DecLineAttr),
 
     /// Ansi sequence is not complete / has errors
     Incomplete,
 
-    Unknown(String),
+    /// A sequence that couldn't be parsed. `raw` is the exact input it was read from, so it can
+    /// be passed through as is, `reason` describes what went wrong.
+    Unknown { raw: String, reason: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ControlChar {
+    /// BEL, `\x07`
+    Bell,
+    /// BS, `\x08`
+    Backspace,
+    /// HT, `\x09`
+    Tab,
+    /// LF, `\x0A`
+    LineFeed,
+    /// CR, `\x0D`
+    CarriageReturn,
+}
+
+impl ControlChar {
+    /// Also matches the `\r\n` grapheme, as its leading carriage return.
+    pub fn from_grapheme(gr: &str) -> Option<ControlChar> {
+        match gr {
+            "\x07" => Some(ControlChar::Bell),
+            "\x08" => Some(ControlChar::Backspace),
+            "\x09" => Some(ControlChar::Tab),
+            "\x0A" => Some(ControlChar::LineFeed),
+            "\x0D" | "\r\n" => Some(ControlChar::CarriageReturn),
+            _ => None,
+        }
+    }
+
+    pub fn as_char(&self) -> char {
+        match self {
+            ControlChar::Bell => '\x07',
+            ControlChar::Backspace => '\x08',
+            ControlChar::Tab => '\x09',
+            ControlChar::LineFeed => '\x0A',
+            ControlChar::CarriageReturn => '\x0D',
+        }
+    }
 }
 
 impl From<char> for AnsiType {
@@ -51,14 +114,19 @@ impl From<char> for AnsiType {
             'N' =>  { AnsiType::SS2 }
             'O' =>  { AnsiType::SS3 }
             'P' =>  { AnsiType::DCS }
-            '[' =>  { AnsiType::CSI { kind: CSIType::Unknown(String::new()) } }
+            '[' =>  { AnsiType::CSI { kind: CSIType::unknown(String::new()) } }
             '\\' => { AnsiType::ST }
-            ']' =>  { AnsiType::OSC { kind: OSCType::Unknown(String::new()) } }
+            ']' =>  { AnsiType::OSC { kind: OSCType::unknown(String::new()) } }
             'X' =>  { AnsiType::SOS }
-            '*' =>  { AnsiType::PM }
+            '^' =>  { AnsiType::PM }
             '_' =>  { AnsiType::APC }
             'c' =>  { AnsiType::RIS }
-            _ => { AnsiType::Unknown(String::from(format!("Unknown ansi escape char: {}", ch))) }
+            'D' =>  { AnsiType::Index }
+            'M' =>  { AnsiType::ReverseIndex }
+            'E' =>  { AnsiType::NextLine }
+            '=' =>  { AnsiType::KeypadApplication }
+            '>' =>  { AnsiType::KeypadNumeric }
+            _ => { AnsiType::unknown(format!("Unknown ansi escape char: {}", ch)) }
         }
     }
 }
@@ -69,29 +137,272 @@ impl From<&str> for AnsiType {
             "N" =>  { AnsiType::SS2 }
             "O" =>  { AnsiType::SS3 }
             "P" =>  { AnsiType::DCS }
-            "[" =>  { AnsiType::CSI { kind: CSIType::Unknown(String::new()) } }
-            "]" =>  { AnsiType::OSC { kind: OSCType::Unknown(String::new()) } }
+            "[" =>  { AnsiType::CSI { kind: CSIType::unknown(String::new()) } }
+            "]" =>  { AnsiType::OSC { kind: OSCType::unknown(String::new()) } }
             "\\" => { AnsiType::ST }
             "X" =>  { AnsiType::SOS }
-            "*" =>  { AnsiType::PM }
+            "^" =>  { AnsiType::PM }
             "_" =>  { AnsiType::APC }
             "c" =>  { AnsiType::RIS }
-            ")" =>  { AnsiType::SETCHARSET }
-            "(" =>  { AnsiType::SETCHARSET }
-            _ => { AnsiType::Unknown(String::from(format!("Unknown ansi escape char: {}", gr))) }
+            "D" =>  { AnsiType::Index }
+            "M" =>  { AnsiType::ReverseIndex }
+            "E" =>  { AnsiType::NextLine }
+            "=" =>  { AnsiType::KeypadApplication }
+            ">" =>  { AnsiType::KeypadNumeric }
+            "(" | ")" | "*" | "+" => { AnsiType::SETCHARSET }
+            // The final byte decides which one it is, see `finish`
+            "#" =>  { AnsiType::DecLineAttr(DecLineAttr::SingleWidth) }
+            _ => { AnsiType::unknown(format!("Unknown ansi escape char: {}", gr)) }
         }
     }
 }
 
 impl AnsiType {
+    /// Returns the type introduced by byte `b` following ESC, or by `b` alone if it is one of the
+    /// C1 introducers 0x80 - 0x9F.
+    pub fn from_byte(b: u8) -> AnsiType {
+        // C1 controls are the 7 bit `ESC x` forms shifted up by 0x40
+        let b = if (0x80..=0x9F).contains(&b) { b - 0x40 } else { b };
+        if !b.is_ascii() {
+            return AnsiType::unknown(format!("Unknown ansi escape byte: {:#04x}", b));
+        }
+        AnsiType::from(char::from(b).encode_utf8(&mut [0; 4]) as &str)
+    }
+
+    /// Returns the text payload if this is `Text`, without cloning it.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            AnsiType::Text(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Returns how many columns the text payload occupies if this is `Text`.
+    #[cfg(feature = "unicode-width")]
+    pub fn display_width(&self) -> Option<usize> {
+        self.text().map(text_width)
+    }
+
+    /// `Unknown` with an empty `raw`, the parsers fill it in with the consumed input.
+    fn unknown(reason: String) -> AnsiType {
+        AnsiType::Unknown { raw: String::new(), reason }
+    }
+
+    /// Sets `raw` of any of the `Unknown` variants to `input()`, if it isn't set yet.
+    fn with_raw(mut self, input: impl FnOnce() -> String) -> AnsiType {
+        match &mut self {
+            AnsiType::Unknown { raw, .. }
+            | AnsiType::CSI { kind: CSIType::Unknown { raw, .. } }
+            | AnsiType::OSC { kind: OSCType::Unknown { raw, .. } } if raw.is_empty() => *raw = input(),
+            _ => {}
+        }
+        self
+    }
+
+    /// Returns the owned text payload if this is `Text`.
+    pub fn into_text(self) -> Option<String> {
+        match self {
+            AnsiType::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Moves the cursor to `row`, `col` (CUP). Top left of the screen is 1,1.
+    pub fn cursor_to(row: usize, col: usize) -> AnsiType {
+        AnsiType::CSI { kind: CSIType::CUP(row, col) }
+    }
+
+    /// Clears the entire screen (ED 2).
+    pub fn clear_screen() -> AnsiType {
+        AnsiType::CSI { kind: CSIType::ED(2) }
+    }
+
+    /// Sets colors and style of the characters following (SGR).
+    pub fn sgr(codes: &[usize]) -> AnsiType {
+        AnsiType::CSI { kind: CSIType::SGR(SgrParams::from(codes)) }
+    }
+
+    /// Sets the title of the terminal window (OSC 0).
+    pub fn set_title(title: &str) -> AnsiType {
+        AnsiType::OSC { kind: OSCType::WindowTitle(String::from(title)) }
+    }
+
+    /// Encodes this back into the sequence the parser would read it from.
+    ///
+    /// `Unknown`, as well as the `CSIType::Unknown` and `OSCType::Unknown` ones, encodes back to its
+    /// raw input. `Incomplete` encodes to nothing.
+    pub fn encode(&self) -> String {
+        match self {
+            AnsiType::Text(s) => s.clone(),
+            AnsiType::Control(c) => String::from(c.as_char()),
+            AnsiType::SS2 => String::from("\x1BN"),
+            AnsiType::SS3 => String::from("\x1BO"),
+            AnsiType::DCS => String::from("\x1BP"),
+            AnsiType::CSI { kind } => {
+                let csi = match kind {
+                    CSIType::CUU(n) => format!("{}A", n),
+                    CSIType::CUD(n) => format!("{}B", n),
+                    CSIType::CUF(n) => format!("{}C", n),
+                    CSIType::CUB(n) => format!("{}D", n),
+                    CSIType::CNL(n) => format!("{}E", n),
+                    CSIType::CPL(n) => format!("{}F", n),
+                    CSIType::CHA(n) => format!("{}G", n),
+                    CSIType::CVA(n) => format!("{}d", n),
+                    CSIType::CUP(n, m) => format!("{};{}H", n, m),
+                    CSIType::ED(n) => format!("{}J", n),
+                    CSIType::EL(n) => format!("{}K", n),
+                    CSIType::SU(n) => format!("{}S", n),
+                    CSIType::SD(n) => format!("{}T", n),
+                    CSIType::SL(n) => format!("{} @", n),
+                    CSIType::SR(n) => format!("{} A", n),
+                    CSIType::IL(n) => format!("{}L", n),
+                    CSIType::DL(n) => format!("{}M", n),
+                    CSIType::HVP(n, m) => format!("{};{}f", n, m),
+                    CSIType::SGR(n) => {
+                        let mut params = String::new();
+                        for (i, code) in n.iter().enumerate() {
+                            if i > 0 {
+                                params.push(if n.is_sub(i) { ':' } else { ';' });
+                            }
+                            params.push_str(&format!("{}", code));
+                        }
+                        format!("{}m", params)
+                    }
+                    CSIType::DECTCEM(show) => format!("?25{}", if *show {"h"} else {"l"}),
+                    CSIType::DECSET(modes) => format!("?{}h", modes.iter().map(|m| format!("{}", m.mode())).collect::<Vec<String>>().join(";")),
+                    CSIType::DECRST(modes) => format!("?{}l", modes.iter().map(|m| format!("{}", m.mode())).collect::<Vec<String>>().join(";")),
+                    CSIType::DECSTBM(n, m) => format!("{};{}r", n, m),
+                    CSIType::DECSLRM(n, m) => format!("{};{}s", n, m),
+                    CSIType::SCOSC => String::from("s"),
+                    CSIType::SCORC => String::from("u"),
+                    CSIType::WindowOp(n) => format!("{}t", n.iter().map(|p| format!("{}", p)).collect::<Vec<String>>().join(";")),
+                    CSIType::DECSTR => String::from("!p"),
+                    CSIType::PushSgr => String::from("#p"),
+                    CSIType::PopSgr => String::from("#q"),
+                    CSIType::DECSCUSR(n) => format!("{} q", n),
+                    CSIType::DECSCA(n) => format!("{}\"q", n),
+                    CSIType::MouseSgr { button, x, y, pressed } => format!("<{};{};{}{}", button, x, y, if *pressed {"M"} else {"m"}),
+                    CSIType::DecRequestMode(mode) => format!("?{}$p", mode),
+                    CSIType::DSR(DsrRequest::Status) => String::from("5n"),
+                    CSIType::DSR(DsrRequest::CursorPosition) => String::from("6n"),
+                    CSIType::DSR(DsrRequest::ExtendedCursorPosition) => String::from("?6n"),
+                    CSIType::MouseX10 { button, x, y } => {
+                        let payload = [*button as usize, *x, *y].map(CSIType::x10_char);
+                        format!("M{}{}{}", payload[0], payload[1], payload[2])
+                    }
+                    CSIType::Unknown { raw, .. } => return raw.clone(),
+                };
+                format!("\x1B[{}", csi)
+            }
+            AnsiType::ST => String::from("\x1B\\"),
+            AnsiType::OSC { kind } => match kind {
+                OSCType::WindowTitle(title) => format!("\x1B]0;{}\x07", title),
+                OSCType::Clipboard { selection, data } => format!("\x1B]52;{};{}\x07", selection, data),
+                OSCType::Hyperlink { params, uri } => format!("\x1B]8;{};{}\x07", params, uri),
+                OSCType::WorkingDirectory { uri } => format!("\x1B]7;{}\x07", uri),
+                OSCType::Notification { title: None, body } => format!("\x1B]9;{}\x07", body),
+                OSCType::Notification { title: Some(title), body } => format!("\x1B]777;notify;{};{}\x07", title, body),
+                OSCType::Unknown { raw, .. } => raw.clone(),
+            },
+            AnsiType::RIS => String::from("\x1Bc"),
+            AnsiType::Index => String::from("\x1BD"),
+            AnsiType::ReverseIndex => String::from("\x1BM"),
+            AnsiType::NextLine => String::from("\x1BE"),
+            AnsiType::KeypadApplication => String::from("\x1B="),
+            AnsiType::KeypadNumeric => String::from("\x1B>"),
+            AnsiType::SOS => String::from("\x1BX"),
+            AnsiType::PM => String::from("\x1B^"),
+            AnsiType::APC => String::from("\x1B_"),
+            // The charset itself isn't kept, so assume the usual switch back to ASCII
+            AnsiType::SETCHARSET => String::from("\x1B(B"),
+            AnsiType::DesignateCharset { slot, charset } => format!("\x1B{}{}", slot.intermediate(), charset.final_byte()),
+            AnsiType::DecAlignmentTest => String::from("\x1B#8"),
+            AnsiType::DecLineAttr(attr) => format!("\x1B#{}", attr.final_byte()),
+            AnsiType::Incomplete => String::new(),
+            AnsiType::Unknown { raw, .. } => raw.clone(),
+        }
+    }
+
+    /// Same as `==`, except that any two `Unknown`s count as equal no matter what they carry,
+    /// including the `CSIType::Unknown` and `OSCType::Unknown` ones.
+    pub fn eq_ignoring_unknown_message(&self, other: &Self) -> bool {
+        match (self, other) {
+            (AnsiType::Unknown { .. }, AnsiType::Unknown { .. }) => true,
+            (AnsiType::CSI { kind: CSIType::Unknown { .. } }, AnsiType::CSI { kind: CSIType::Unknown { .. } }) => true,
+            (AnsiType::OSC { kind: OSCType::Unknown { .. } }, AnsiType::OSC { kind: OSCType::Unknown { .. } }) => true,
+            _ => self == other,
+        }
+    }
+
+    /// Same as `encode`, as UTF-8 bytes.
+    pub fn encode_bytes(&self) -> Vec<u8> {
+        self.encode().into_bytes()
+    }
+
+    /// Returns `encode().len()` without building the string.
+    pub fn byte_len(&self) -> usize {
+        match self {
+            AnsiType::Text(s) => s.len(),
+            AnsiType::Control(c) => c.as_char().len_utf8(),
+            AnsiType::CSI { kind } => {
+                let csi = match kind {
+                    CSIType::CUU(n) | CSIType::CUD(n) | CSIType::CUF(n) | CSIType::CUB(n)
+                    | CSIType::CNL(n) | CSIType::CPL(n) | CSIType::CHA(n) | CSIType::CVA(n)
+                    | CSIType::ED(n) | CSIType::EL(n) | CSIType::SU(n) | CSIType::SD(n)
+                    | CSIType::IL(n) | CSIType::DL(n) => digits(*n) + 1,
+                    CSIType::SL(n) | CSIType::SR(n) | CSIType::DECSCUSR(n) | CSIType::DECSCA(n) => digits(*n) + 2,
+                    CSIType::CUP(n, m) | CSIType::HVP(n, m) | CSIType::DECSTBM(n, m) | CSIType::DECSLRM(n, m) => {
+                        digits(*n) + 1 + digits(*m) + 1
+                    }
+                    CSIType::SGR(n) => {
+                        // `:` and `;` are both one byte
+                        joined_len(n.iter().map(|code| digits(*code))) + 1
+                    }
+                    CSIType::DECTCEM(_) => 4,
+                    CSIType::DECSET(modes) | CSIType::DECRST(modes) => {
+                        1 + joined_len(modes.iter().map(|m| digits(m.mode()))) + 1
+                    }
+                    CSIType::SCOSC | CSIType::SCORC => 1,
+                    CSIType::WindowOp(n) => joined_len(n.iter().map(|p| digits(*p))) + 1,
+                    CSIType::DECSTR | CSIType::PushSgr | CSIType::PopSgr => 2,
+                    CSIType::MouseSgr { button, x, y, .. } => 1 + digits(*button) + 1 + digits(*x) + 1 + digits(*y) + 1,
+                    CSIType::DecRequestMode(mode) => 1 + digits(*mode) + 2,
+                    CSIType::DSR(DsrRequest::ExtendedCursorPosition) => 3,
+                    CSIType::DSR(_) => 2,
+                    CSIType::MouseX10 { button, x, y } => {
+                        1 + [*button as usize, *x, *y].iter()
+                            .map(|n| CSIType::x10_char(*n).len_utf8())
+                            .sum::<usize>()
+                    }
+                    CSIType::Unknown { raw, .. } => return raw.len(),
+                };
+                2 + csi
+            }
+            AnsiType::OSC { kind } => match kind {
+                OSCType::WindowTitle(title) => 4 + title.len() + 1,
+                OSCType::Clipboard { selection, data } => 5 + selection.len() + 1 + data.len() + 1,
+                OSCType::Hyperlink { params, uri } => 4 + params.len() + 1 + uri.len() + 1,
+                OSCType::WorkingDirectory { uri } => 4 + uri.len() + 1,
+                OSCType::Notification { title: None, body } => 4 + body.len() + 1,
+                OSCType::Notification { title: Some(title), body } => 13 + title.len() + 1 + body.len() + 1,
+                OSCType::Unknown { raw, .. } => raw.len(),
+            },
+            AnsiType::SETCHARSET | AnsiType::DecAlignmentTest | AnsiType::DecLineAttr(_) => 3,
+            AnsiType::DesignateCharset { charset, .. } => 2 + charset.final_byte().len_utf8(),
+            AnsiType::Incomplete => 0,
+            AnsiType::Unknown { raw, .. } => raw.len(),
+            // Everything else is ESC and a single character
+            _ => 2,
+        }
+    }
+
     pub fn finish(gr: &str, t: AnsiType, args: Vec<String>) -> AnsiType {
         match t {
             AnsiType::SS2 => {AnsiType::SS2}
             AnsiType::SS3 => {AnsiType::SS3}
             AnsiType::DCS => {AnsiType::DCS}
             AnsiType::CSI { .. } => {
-                let csi = AnsiType::CSI { kind: CSIType::from(gr, args) };
-                csi
+                AnsiType::CSI { kind: CSIType::from(gr, args) }
             }
             AnsiType::ST => {AnsiType::ST}
             AnsiType::OSC { .. } => {
@@ -99,13 +410,20 @@ impl AnsiType {
                 AnsiType::OSC {kind: OSCType::from(gr, args)}
             }
             AnsiType::RIS => {AnsiType::RIS}
+            AnsiType::Index | AnsiType::ReverseIndex | AnsiType::NextLine => t,
+            AnsiType::KeypadApplication => t,
+            AnsiType::KeypadNumeric => t,
             AnsiType::SOS => {AnsiType::SOS}
             AnsiType::PM => {AnsiType::PM}
             AnsiType::APC => {AnsiType::APC}
             AnsiType::Incomplete => {AnsiType::Incomplete}
-            AnsiType::Unknown(s) => {AnsiType::Unknown(s)}
+            AnsiType::Unknown { .. } => t,
             AnsiType::Text(s) => {AnsiType::Text(s)}
+            AnsiType::Control(_) => t,
             AnsiType::SETCHARSET => t,
+            AnsiType::DesignateCharset { .. } => t,
+            AnsiType::DecAlignmentTest => t,
+            AnsiType::DecLineAttr(_) => Self::finish_dec_line(gr),
         }
     }
 
@@ -115,107 +433,538 @@ impl AnsiType {
             AnsiType::SS3 => {AnsiType::SS3}
             AnsiType::DCS => {AnsiType::DCS}
             AnsiType::CSI { .. } => {
-                let csi = AnsiType::CSI { kind: CSIType::from_grapheme(gr, args) };
-                csi
+                AnsiType::CSI { kind: CSIType::from_grapheme(gr, args) }
             }
             AnsiType::ST => {AnsiType::ST}
             AnsiType::OSC { .. } => {AnsiType::OSC {kind: OSCType::from_grapheme(gr, args)}}
             AnsiType::RIS => {AnsiType::RIS}
+            AnsiType::Index | AnsiType::ReverseIndex | AnsiType::NextLine => t,
+            AnsiType::KeypadApplication => t,
+            AnsiType::KeypadNumeric => t,
             AnsiType::SOS => {AnsiType::SOS}
             AnsiType::PM => {AnsiType::PM}
             AnsiType::APC => {AnsiType::APC}
             AnsiType::Incomplete => {AnsiType::Incomplete}
-            AnsiType::Unknown(s) => {AnsiType::Unknown(s)}
+            AnsiType::Unknown { .. } => t,
             AnsiType::Text(s) => {AnsiType::Text(s)}
+            AnsiType::Control(_) => t,
             AnsiType::SETCHARSET => t,
+            AnsiType::DesignateCharset { .. } => t,
+            AnsiType::DecAlignmentTest => t,
+            AnsiType::DecLineAttr(_) => Self::finish_dec_line(gr),
+        }
+    }
+
+    /// Builds the designation from the slot intermediate (`(`, `)`, `*`, `+`) and the final byte.
+    fn designate_charset(intermediate: &str, gr: &str) -> AnsiType {
+        let slot = intermediate.chars().next().and_then(CharsetSlot::from_intermediate);
+        match (slot, gr.chars().next()) {
+            (Some(slot), Some(ch)) if gr.len() == 1 => AnsiType::DesignateCharset { slot, charset: Charset::from(ch) },
+            _ => AnsiType::unknown(format!("Unknown character set: {}{}", intermediate, gr)),
+        }
+    }
+
+    /// Picks the `ESC #` sequence from its final byte.
+    fn finish_dec_line(gr: &str) -> AnsiType {
+        match gr.chars().next().and_then(DecLineAttr::from_final_byte) {
+            Some(attr) => AnsiType::DecLineAttr(attr),
+            None if gr == "8" => AnsiType::DecAlignmentTest,
+            None => AnsiType::unknown(format!("Unknown ansi escape char: #{}", gr)),
         }
     }
 
     pub fn valid_char_ranges(t: &AnsiType) -> (Range<u32>, Range<u32>) {
-        let mut end_char_range = 1..0;
+        let mut end_char_range = 0..0;
         (match t {
-            AnsiType::Text(_) => {1..0}
-            AnsiType::SS2 => {1..0}
-            AnsiType::SS3 => {1..0}
-            AnsiType::DCS => {1..0}
+            AnsiType::Text(_) => {0..0}
+            AnsiType::Control(_) => {0..0}
+            AnsiType::SS2 => {0..0}
+            AnsiType::SS3 => {0..0}
+            AnsiType::DCS => {0..0}
             AnsiType::CSI { .. } => {end_char_range = 0x40..0x80; 0x20..0x40}
-            AnsiType::ST => {1..0}
-            AnsiType::OSC { .. } => {end_char_range = 0x7..0x8; 0x20..0x80}
-            AnsiType::RIS => {1..0}
-            AnsiType::SOS => {1..0}
-            AnsiType::PM => {1..0}
-            AnsiType::APC => {1..0}
-            AnsiType::Incomplete => {1..0}
-            AnsiType::SETCHARSET => {1..0}
-            AnsiType::Unknown(_) => {1..0}
+            AnsiType::ST => {0..0}
+            // OSC strings can hold any text, up to the BEL or ST terminator
+            AnsiType::OSC { .. } => {end_char_range = 0x7..0x8; 0x20..0x110000}
+            AnsiType::RIS => {0..0}
+            AnsiType::Index | AnsiType::ReverseIndex | AnsiType::NextLine => {0..0}
+            AnsiType::KeypadApplication => {0..0}
+            AnsiType::KeypadNumeric => {0..0}
+            AnsiType::SOS => {0..0}
+            AnsiType::PM => {0..0}
+            AnsiType::APC => {0..0}
+            AnsiType::Incomplete => {0..0}
+            AnsiType::SETCHARSET => {0..0}
+            AnsiType::DesignateCharset { .. } => {0..0}
+            AnsiType::DecAlignmentTest => {0..0}
+            AnsiType::DecLineAttr(_) => {0..0}
+            AnsiType::Unknown { .. } => {0..0}
         }, end_char_range)
     }
 }
 
+/// Same as `AnsiType::encode`.
+impl From<AnsiType> for String {
+    fn from(ansi: AnsiType) -> Self {
+        ansi.encode()
+    }
+}
+
+/// Same as `AnsiType::encode`.
+impl From<&AnsiType> for String {
+    fn from(ansi: &AnsiType) -> Self {
+        ansi.encode()
+    }
+}
+
 impl Display for AnsiType {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        let _ = match self {
-            AnsiType::Text(s) => f.write_str(format!("Text({:?})", s).as_str()),
+        match self {
+            AnsiType::Text(s) => write!(f, "Text({:?})", s),
+            AnsiType::Control(c) => write!(f, "Control({:?})", c),
             AnsiType::SS2 => {f.write_str("SS2")}
             AnsiType::SS3 => {f.write_str("SS3")}
             AnsiType::DCS => {f.write_str("DCS")}
-            AnsiType::CSI { kind } => {
-                let _ = match kind {
-                    CSIType::CUU(n) => {
-                        f.write_str(format!("CUU {{ n: {}", n).as_str())
-                    }
-                    CSIType::CUD(n) => {
-                        f.write_str(format!("CUD {{ n: {}", n).as_str())
-                    }
-                    CSIType::CUF(n) => {f.write_str(format!("CUF {{ n: {}", n).as_str())}
-                    CSIType::CUB(n) => {f.write_str(format!("CUB {{ n: {}", n).as_str())}
-                    CSIType::CNL(n) => {f.write_str(format!("CNL {{ n: {}", n).as_str())}
-                    CSIType::CPL(n) => {f.write_str(format!("CPL {{ n: {}", n).as_str())}
-                    CSIType::CHA(n) => {f.write_str(format!("CHA {{ n: {}", n).as_str())}
-                    CSIType::CVA(n) => {f.write_str(format!("CVA {{ n: {}", n).as_str())}
-                    CSIType::CUP(n, m) => {f.write_str(format!("CUP {{ n: {}, m: {}", n, m).as_str())}
-                    CSIType::ED(n) => {f.write_str(format!("ED {{ n: {}", n).as_str())}
-                    CSIType::EL(n) => {f.write_str(format!("EL {{ n: {}", n).as_str())}
-                    CSIType::SU(n) => {f.write_str(format!("SU {{ n: {}", n).as_str())}
-                    CSIType::SD(n) => {f.write_str(format!("SD {{ n: {}", n).as_str())}
-                    CSIType::IL(n) => {f.write_str(format!("IL {{ n: {}", n).as_str())}
-                    CSIType::HVP(n, m) => {f.write_str(format!("HVP {{ n: {}, m: {}", n, m).as_str())}
-                    CSIType::SGR(n) => {f.write_str(format!("SGR {{ n: {:?}", n).as_str())}
-                    CSIType::DECSTBM(n, m) => {f.write_str(format!("DECSTBM {{ n: {}, m: {:?}", n, m).as_str())}
-                    CSIType::DECSLRM(n, m) => {f.write_str(format!("DECSLRM {{ n: {}, m: {:?}", n, m).as_str())}
-                    CSIType::DECTCEM(h) => {f.write_str(format!("DECTCEM {{ h: {:?}", h).as_str())}
-                    CSIType::Unknown(s) => {f.write_str(format!("CSI {{ Unknown: {:?}", s).as_str())}
-                };
-                f.write_str(" }")
-            } // End CSI
-
+            AnsiType::CSI { kind } => write!(f, "{}", kind),
             AnsiType::ST => {f.write_str("ST")}
-            AnsiType::OSC { kind } => {
-                let _ = match kind {
-                    OSCType::WindowTitle(s) => {f.write_str(format!("OSC {{ WindowTitle: {:?}", s).as_str())}
-                    OSCType::Unknown(s) => {f.write_str(format!("OSC {{ Unknown: {:?}", s).as_str())}
-                };
-                f.write_str(" }")
-            }
+            AnsiType::OSC { kind } => write!(f, "{}", kind),
             AnsiType::RIS => {f.write_str("RIS")}
+            AnsiType::Index => {f.write_str("Index")}
+            AnsiType::ReverseIndex => {f.write_str("ReverseIndex")}
+            AnsiType::NextLine => {f.write_str("NextLine")}
+            AnsiType::KeypadApplication => {f.write_str("KeypadApplication")}
+            AnsiType::KeypadNumeric => {f.write_str("KeypadNumeric")}
             AnsiType::SOS => {f.write_str("SOS")}
             AnsiType::PM => {f.write_str("PM")}
             AnsiType::APC => {f.write_str("APC")}
-            AnsiType::Unknown(s) => {f.write_str(format!("Unknown: {:?}", s).as_str())}
+            AnsiType::Unknown { reason, .. } => {write!(f, "Unknown: {:?}", reason)}
             AnsiType::Incomplete => {f.write_str("Incomplete")}
             AnsiType::SETCHARSET => f.write_str("TODO"),
-        };
-        Ok(())
+            AnsiType::DesignateCharset { slot, charset } => write!(f, "DesignateCharset {{ slot: {:?}, charset: {:?} }}", slot, charset),
+            AnsiType::DecAlignmentTest => f.write_str("DecAlignmentTest"),
+            AnsiType::DecLineAttr(attr) => write!(f, "DecLineAttr({:?})", attr),
+        }
+    }
+}
+
+impl Display for CSIType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            CSIType::CUU(n) => {
+                write!(f, "CUU {{ n: {}", n)
+            }
+            CSIType::CUD(n) => {
+                write!(f, "CUD {{ n: {}", n)
+            }
+            CSIType::CUF(n) => {write!(f, "CUF {{ n: {}", n)}
+            CSIType::CUB(n) => {write!(f, "CUB {{ n: {}", n)}
+            CSIType::CNL(n) => {write!(f, "CNL {{ n: {}", n)}
+            CSIType::CPL(n) => {write!(f, "CPL {{ n: {}", n)}
+            CSIType::CHA(n) => {write!(f, "CHA {{ n: {}", n)}
+            CSIType::CVA(n) => {write!(f, "CVA {{ n: {}", n)}
+            CSIType::CUP(n, m) => {write!(f, "CUP {{ n: {}, m: {}", n, m)}
+            CSIType::ED(n) => {write!(f, "ED {{ n: {}", n)}
+            CSIType::EL(n) => {write!(f, "EL {{ n: {}", n)}
+            CSIType::SU(n) => {write!(f, "SU {{ n: {}", n)}
+            CSIType::SD(n) => {write!(f, "SD {{ n: {}", n)}
+            CSIType::SL(n) => {write!(f, "SL {{ n: {}", n)}
+            CSIType::SR(n) => {write!(f, "SR {{ n: {}", n)}
+            CSIType::IL(n) => {write!(f, "IL {{ n: {}", n)}
+            CSIType::DL(n) => {write!(f, "DL {{ n: {}", n)}
+            CSIType::HVP(n, m) => {write!(f, "HVP {{ n: {}, m: {}", n, m)}
+            CSIType::SGR(n) => {write!(f, "SGR {{ n: {:?}", n)}
+            CSIType::DECSTBM(n, m) => {write!(f, "DECSTBM {{ n: {}, m: {:?}", n, m)}
+            CSIType::DECSLRM(n, m) => {write!(f, "DECSLRM {{ n: {}, m: {:?}", n, m)}
+            CSIType::SCOSC => {f.write_str("SCOSC {")}
+            CSIType::SCORC => {f.write_str("SCORC {")}
+            CSIType::WindowOp(n) => {write!(f, "WindowOp {{ n: {:?}", n)}
+            CSIType::DECTCEM(h) => {write!(f, "DECTCEM {{ h: {:?}", h)}
+            CSIType::DECSET(modes) => {write!(f, "DECSET {{ modes: {:?}", modes)}
+            CSIType::DECRST(modes) => {write!(f, "DECRST {{ modes: {:?}", modes)}
+            CSIType::DECSTR => {f.write_str("DECSTR {")}
+            CSIType::PushSgr => {f.write_str("PushSgr {")}
+            CSIType::PopSgr => {f.write_str("PopSgr {")}
+            CSIType::DECSCUSR(n) => {write!(f, "DECSCUSR {{ n: {}", n)}
+            CSIType::DECSCA(n) => {write!(f, "DECSCA {{ n: {}", n)}
+            CSIType::MouseSgr { button, x, y, pressed } => {
+                write!(f, "MouseSgr {{ button: {}, x: {}, y: {}, pressed: {}", button, x, y, pressed)
+            }
+            CSIType::MouseX10 { button, x, y } => {
+                write!(f, "MouseX10 {{ button: {}, x: {}, y: {}", button, x, y)
+            }
+            CSIType::DecRequestMode(mode) => write!(f, "DecRequestMode {{ mode: {}", mode),
+            CSIType::DSR(request) => write!(f, "DSR {{ request: {:?}", request),
+            CSIType::Unknown { reason, .. } => {write!(f, "CSI {{ Unknown: {:?}", reason)}
+        }?;
+        f.write_str(" }")
+    }
+}
+
+impl Display for OSCType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            OSCType::WindowTitle(s) => {write!(f, "OSC {{ WindowTitle: {:?}", s)}
+            OSCType::Clipboard { selection, data } => {write!(f, "OSC {{ Clipboard: {:?}, {:?}", selection, data)}
+            OSCType::Hyperlink { params, uri } => {write!(f, "OSC {{ Hyperlink: {:?}, {:?}", params, uri)}
+            OSCType::WorkingDirectory { uri } => {write!(f, "OSC {{ WorkingDirectory: {:?}", uri)}
+            OSCType::Notification { title, body } => {write!(f, "OSC {{ Notification: {:?}, {:?}", title, body)}
+            OSCType::Unknown { reason, .. } => {write!(f, "OSC {{ Unknown: {:?}", reason)}
+        }?;
+        f.write_str(" }")
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OSCType {
     WindowTitle(String),
-    Unknown(String),
+    /// Sets the clipboard (`OSC 52 ; selection ; data`). `data` is base64, or `?` to query it.
+    Clipboard { selection: String, data: String },
+    /// Starts a hyperlink (`OSC 8 ; params ; uri`), an empty `uri` ends it.
+    Hyperlink { params: String, uri: String },
+    /// The shell's current directory (`OSC 7 ; uri`), a `file://host/path` URI kept as sent,
+    /// percent-encoding included.
+    WorkingDirectory { uri: String },
+    /// Desktop notification, either `OSC 9 ; body` (iTerm2, ConEmu) without a title or
+    /// `OSC 777 ; notify ; title ; body` (urxvt).
+    Notification { title: Option<String>, body: String },
+    /// An OSC command that isn't supported, `raw` and `reason` like in `AnsiType::Unknown`.
+    Unknown { raw: String, reason: String },
+}
+
+/// Which OSC commands get parsed. Disabled ones come out as `OSCType::Unknown`, so they can be
+/// dropped by programs that don't want the output they display to e.g. touch the clipboard.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OscConfig {
+    /// OSC 0, window title
+    pub allow_title: bool,
+    /// OSC 52, clipboard access
+    pub allow_clipboard: bool,
+    /// OSC 8, hyperlinks
+    pub allow_hyperlink: bool,
+    /// OSC 9 and 777, desktop notifications
+    pub allow_notification: bool,
+}
+
+impl Default for OscConfig {
+    /// Everything allowed.
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OscConfig {
+    /// Everything allowed.
+    pub const fn new() -> Self {
+        Self {
+            allow_title: true,
+            allow_clipboard: true,
+            allow_hyperlink: true,
+            allow_notification: true,
+        }
+    }
+
+    /// Turns `ansi` into `OSCType::Unknown` if it is a disabled OSC command. Its `raw` is left
+    /// empty, so passing the unknown sequences through doesn't let the disabled ones slip by.
+    pub fn filter(&self, ansi: AnsiType) -> AnsiType {
+        let (allowed, command) = match &ansi {
+            AnsiType::OSC { kind: OSCType::WindowTitle(_) } => (self.allow_title, 0),
+            AnsiType::OSC { kind: OSCType::Clipboard { .. } } => (self.allow_clipboard, 52),
+            AnsiType::OSC { kind: OSCType::Hyperlink { .. } } => (self.allow_hyperlink, 8),
+            AnsiType::OSC { kind: OSCType::Notification { title: None, .. } } => (self.allow_notification, 9),
+            AnsiType::OSC { kind: OSCType::Notification { .. } } => (self.allow_notification, 777),
+            _ => return ansi,
+        };
+        if allowed {
+            ansi
+        } else {
+            AnsiType::OSC { kind: OSCType::unknown(format!("Disabled OSC command: {}", command)) }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DecPrivateMode {
+    /// Mode 6, cursor addressing relative to the scroll region (DECOM)
+    Origin,
+    /// Mode 7, wrap text at the right margin (DECAWM)
+    AutoWrap,
+    /// Mode 12, blinking cursor
+    CursorBlink,
+    /// Mode 25, visible cursor
+    CursorVisible,
+    Unknown(usize),
+}
+
+impl From<usize> for DecPrivateMode {
+    fn from(n: usize) -> Self {
+        match n {
+            6 => DecPrivateMode::Origin,
+            7 => DecPrivateMode::AutoWrap,
+            12 => DecPrivateMode::CursorBlink,
+            25 => DecPrivateMode::CursorVisible,
+            _ => DecPrivateMode::Unknown(n),
+        }
+    }
+}
+
+impl DecPrivateMode {
+    /// Returns the mode number.
+    pub fn mode(&self) -> usize {
+        match self {
+            DecPrivateMode::Origin => 6,
+            DecPrivateMode::AutoWrap => 7,
+            DecPrivateMode::CursorBlink => 12,
+            DecPrivateMode::CursorVisible => 25,
+            DecPrivateMode::Unknown(n) => *n,
+        }
+    }
+}
+
+/// Part of the screen or line cleared by `ED` / `EL`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EraseRegion {
+    /// 0, from the cursor to the end
+    ToEnd,
+    /// 1, from the cursor to the beginning
+    ToStart,
+    /// 2, everything
+    All,
+    /// 3, everything including the scrollback buffer, only used by `ED`
+    AllWithScrollback,
+}
+
+impl From<usize> for EraseRegion {
+    /// Values without a meaning map to `ToEnd`, same as the default parameter.
+    fn from(n: usize) -> Self {
+        match n {
+            1 => EraseRegion::ToStart,
+            2 => EraseRegion::All,
+            3 => EraseRegion::AllWithScrollback,
+            _ => EraseRegion::ToEnd,
+        }
+    }
+}
+
+impl EraseRegion {
+    /// Returns the parameter value.
+    pub fn value(&self) -> usize {
+        match self {
+            EraseRegion::ToEnd => 0,
+            EraseRegion::ToStart => 1,
+            EraseRegion::All => 2,
+            EraseRegion::AllWithScrollback => 3,
+        }
+    }
+}
+
+/// Cursor shape set by `DECSCUSR`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CursorStyle {
+    /// 0, whatever the user configured
+    Default,
+    /// 1
+    BlinkingBlock,
+    /// 2
+    SteadyBlock,
+    /// 3
+    BlinkingUnderline,
+    /// 4
+    SteadyUnderline,
+    /// 5
+    BlinkingBar,
+    /// 6
+    SteadyBar,
+}
+
+impl From<usize> for CursorStyle {
+    /// Values above 6 map to `Default`, same as 0.
+    fn from(n: usize) -> Self {
+        match n {
+            1 => CursorStyle::BlinkingBlock,
+            2 => CursorStyle::SteadyBlock,
+            3 => CursorStyle::BlinkingUnderline,
+            4 => CursorStyle::SteadyUnderline,
+            5 => CursorStyle::BlinkingBar,
+            6 => CursorStyle::SteadyBar,
+            _ => CursorStyle::Default,
+        }
+    }
+}
+
+/// What a device status report (`CSI Ps n`) asks for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DsrRequest {
+    /// `CSI 5 n`, answered with [`dsr_ok_response`]
+    Status,
+    /// `CSI 6 n`, answered with `CSI row ; column R`
+    CursorPosition,
+    /// DECXCPR, `CSI ? 6 n`, answered with `CSI ? row ; column ; page R`
+    ExtendedCursorPosition,
+}
+
+/// One of the four slots a character set can be designated to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CharsetSlot {
+    /// `ESC (`
+    G0,
+    /// `ESC )`
+    G1,
+    /// `ESC *`
+    G2,
+    /// `ESC +`
+    G3,
+}
+
+impl CharsetSlot {
+    pub fn from_intermediate(ch: char) -> Option<CharsetSlot> {
+        match ch {
+            '(' => Some(CharsetSlot::G0),
+            ')' => Some(CharsetSlot::G1),
+            '*' => Some(CharsetSlot::G2),
+            '+' => Some(CharsetSlot::G3),
+            _ => None,
+        }
+    }
+
+    pub fn intermediate(&self) -> char {
+        match self {
+            CharsetSlot::G0 => '(',
+            CharsetSlot::G1 => ')',
+            CharsetSlot::G2 => '*',
+            CharsetSlot::G3 => '+',
+        }
+    }
+}
+
+/// Character set selected by its final byte.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Charset {
+    /// US ASCII, `B`
+    Ascii,
+    /// DEC special graphics, the line drawing characters, `0`
+    DecSpecialGraphics,
+    /// British, `A`. Same as ASCII, except `#` is a pound sign
+    Uk,
+    /// Any other final byte
+    Unknown(char),
+}
+
+impl From<char> for Charset {
+    fn from(ch: char) -> Self {
+        match ch {
+            'B' => Charset::Ascii,
+            '0' => Charset::DecSpecialGraphics,
+            'A' => Charset::Uk,
+            _ => Charset::Unknown(ch),
+        }
+    }
+}
+
+impl Charset {
+    /// Returns `text` as it looks in this character set. Only `DecSpecialGraphics` changes
+    /// anything, see [`dec_special_to_unicode`].
+    pub fn translate(&self, text: &str) -> String {
+        match self {
+            Charset::DecSpecialGraphics => text.chars().map(|ch| {
+                if ch.is_ascii() { dec_special_to_unicode(ch as u8) } else { ch }
+            }).collect(),
+            _ => String::from(text),
+        }
+    }
+
+    pub fn final_byte(&self) -> char {
+        match self {
+            Charset::Ascii => 'B',
+            Charset::DecSpecialGraphics => '0',
+            Charset::Uk => 'A',
+            Charset::Unknown(ch) => *ch,
+        }
+    }
+}
+
+/// Maps an ASCII byte to the character it stands for in the DEC special graphics set, e.g. `q`
+/// to `─`. Bytes outside of `0x5F..=0x7E` are the same in both sets.
+pub fn dec_special_to_unicode(b: u8) -> char {
+    match b {
+        b'_' => ' ',
+        b'`' => '◆',
+        b'a' => '▒',
+        b'b' => '␉',
+        b'c' => '␌',
+        b'd' => '␍',
+        b'e' => '␊',
+        b'f' => '°',
+        b'g' => '±',
+        b'h' => '␤',
+        b'i' => '␋',
+        b'j' => '┘',
+        b'k' => '┐',
+        b'l' => '┌',
+        b'm' => '└',
+        b'n' => '┼',
+        b'o' => '⎺',
+        b'p' => '⎻',
+        b'q' => '─',
+        b'r' => '⎼',
+        b's' => '⎽',
+        b't' => '├',
+        b'u' => '┤',
+        b'v' => '┴',
+        b'w' => '┬',
+        b'x' => '│',
+        b'y' => '≤',
+        b'z' => '≥',
+        b'{' => 'π',
+        b'|' => '≠',
+        b'}' => '£',
+        b'~' => '·',
+        _ => b as char,
+    }
+}
+
+/// Line size set by the `ESC #` sequences, applies to the whole line the cursor is on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DecLineAttr {
+    /// DECDHL top half, `ESC # 3`
+    DoubleHeightTop,
+    /// DECDHL bottom half, `ESC # 4`
+    DoubleHeightBottom,
+    /// DECSWL, `ESC # 5`
+    SingleWidth,
+    /// DECDWL, `ESC # 6`
+    DoubleWidth,
+}
+
+impl DecLineAttr {
+    pub fn from_final_byte(ch: char) -> Option<DecLineAttr> {
+        match ch {
+            '3' => Some(DecLineAttr::DoubleHeightTop),
+            '4' => Some(DecLineAttr::DoubleHeightBottom),
+            '5' => Some(DecLineAttr::SingleWidth),
+            '6' => Some(DecLineAttr::DoubleWidth),
+            _ => None,
+        }
+    }
+
+    pub fn final_byte(&self) -> char {
+        match self {
+            DecLineAttr::DoubleHeightTop => '3',
+            DecLineAttr::DoubleHeightBottom => '4',
+            DecLineAttr::SingleWidth => '5',
+            DecLineAttr::DoubleWidth => '6',
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CSIType {
     // Cursor manipulation
     CUU(usize),
@@ -233,84 +982,168 @@ pub enum CSIType {
 
     SU(usize),
     SD(usize),
+    /// Scrolls the page left by `n` columns (`CSI Ps SP @`).
+    SL(usize),
+    /// Scrolls the page right by `n` columns (`CSI Ps SP A`).
+    SR(usize),
 
     IL(usize),
+    /// Deletes `n` lines starting at the cursor (`CSI Ps M`). Without parameters `CSI M` starts
+    /// an X10 mouse report instead, so DL has to be sent with the count.
+    DL(usize),
 
     HVP(usize,usize),
 
-    SGR(Vec<usize>),
+    SGR(SgrParams),
 
     DECTCEM(bool),
+    /// Sets one or more DEC private modes (`CSI ? Pm h`). A lone mode 25 is parsed as `DECTCEM` instead.
+    DECSET(Vec<DecPrivateMode>),
+    /// Resets one or more DEC private modes (`CSI ? Pm l`). A lone mode 25 is parsed as `DECTCEM` instead.
+    DECRST(Vec<DecPrivateMode>),
+    /// Sets the top and bottom margin (`CSI Pt ; Pb r`). A left out bottom margin is 0 for the
+    /// last row, and `CSI r` resetting the region to the whole screen is `DECSTBM(0, 0)`.
     DECSTBM(usize, usize),
     DECSLRM(usize, usize),
+    /// Saves the cursor position (`CSI s` without parameters, from ANSI.SYS).
+    SCOSC,
+    /// Restores the cursor position saved by `SCOSC` (`CSI u`).
+    SCORC,
 
-    Unknown(String),
+    /// XTWINOPS, window manipulation (`CSI Ps ; Ps ; Ps t`). Common operations:
+    ///
+    /// - 1 / 2 - De-iconify / iconify the window
+    /// - 3;x;y - Move the window to x, y
+    /// - 8;h;w - Resize the text area to h rows, w columns
+    /// - 14 / 18 - Report the window size in pixels / the text area size in characters
+    /// - 22;0 / 23;0 - Push / pop the icon and window title
+    WindowOp(Vec<usize>),
+    /// Soft terminal reset (`CSI ! p`).
+    DECSTR,
+    /// Saves the current SGR attributes on a stack (XTPUSHSGR, `CSI # p` or `CSI # {`).
+    PushSgr,
+    /// Restores the attributes saved by the last `PushSgr` (XTPOPSGR, `CSI # q` or `CSI # }`).
+    PopSgr,
+    /// Sets the cursor style (`CSI Ps SP q`), see `CursorStyle` for the values.
+    DECSCUSR(usize),
+    /// Sets whether the characters following are protected from selective erase
+    /// (`CSI Ps " q`), 1 turns protection on, 0 and 2 turn it off.
+    DECSCA(usize),
+    /// SGR mouse report (`CSI < b ; x ; y M`), sent with mode 1006 enabled. `x`, `y` start at 1,1,
+    /// `pressed` is false for the release final `m`.
+    MouseSgr { button: usize, x: usize, y: usize, pressed: bool },
+    /// Legacy X10 / normal mouse report, `CSI M` followed by the button, x and y, each sent as a
+    /// single character offset by 32. `x`, `y` start at 1,1. The characters end at 255, so values
+    /// over 223 are encoded as 223.
+    MouseX10 { button: u8, x: usize, y: usize },
+    /// Asks whether a DEC private mode is set (DECRQM, `CSI ? Ps $ p`), answered with [`decrpm`].
+    DecRequestMode(usize),
+    /// Device status report (`CSI Ps n`), see `DsrRequest`.
+    DSR(DsrRequest),
+
+    /// A CSI command that isn't supported, `raw` and `reason` like in `AnsiType::Unknown`.
+    Unknown { raw: String, reason: String },
 }
 
 impl OSCType {
+    /// `Unknown` with an empty `raw`, the parsers fill it in with the consumed input.
+    fn unknown(reason: String) -> OSCType {
+        OSCType::Unknown { raw: String::new(), reason }
+    }
+
     pub fn from(gr: &str, args: Vec<String>) -> OSCType {
-        match args[0].as_str() {
-            "0" => /* BEL */ {
-                OSCType::WindowTitle(args[1].clone())
+        match args.first().map(String::as_str) {
+            None | Some("") => { OSCType::unknown(format!("OSC command without parameters: {:?}", gr)) }
+            Some("0") => /* BEL */ {
+                // A missing or empty title clears it
+                OSCType::WindowTitle(args.get(1).cloned().unwrap_or_default())
+            }
+            Some("8") => {
+                // The URI can contain semicolons itself
+                OSCType::Hyperlink { params: args.get(1).cloned().unwrap_or_default(), uri: args.get(2..).unwrap_or_default().join(";") }
+            }
+            Some("52") => {
+                OSCType::Clipboard { selection: args.get(1).cloned().unwrap_or_default(), data: args.get(2).cloned().unwrap_or_default() }
+            }
+            Some("7") => {
+                OSCType::WorkingDirectory { uri: args.get(1..).unwrap_or_default().join(";") }
+            }
+            Some("9") => {
+                OSCType::Notification { title: None, body: args.get(1..).unwrap_or_default().join(";") }
             }
-            _ => { OSCType::Unknown(String::from(format!("Unknown OSC command: {:?}", gr)))}
+            Some("777") if args.get(1).map(String::as_str) == Some("notify") => {
+                OSCType::Notification { title: Some(args.get(2).cloned().unwrap_or_default()), body: args.get(3..).unwrap_or_default().join(";") }
+            }
+            _ => { OSCType::unknown(format!("Unknown OSC command: {:?}", gr))}
         }
     }
 
     pub fn from_grapheme(gr: &str, args: Vec<String>) -> OSCType {
-        match args[0].as_str() {
-            "0" => /* BEL */ {
-                OSCType::WindowTitle(args[1].clone())
-            }
-            _ => { OSCType::Unknown(String::from(format!("Unknown OSC command: {:?}", gr)))}
-        }
+        Self::from(gr, args)
     }
 }
 
 impl CSIType {
+    /// `Unknown` with an empty `raw`, the parsers fill it in with the consumed input.
+    fn unknown(reason: String) -> CSIType {
+        CSIType::Unknown { raw: String::new(), reason }
+    }
+
+    /// Decodes the three characters following `CSI M` in an X10 mouse report.
+    pub fn mouse_x10(payload: [char; 3]) -> CSIType {
+        let [button, x, y] = payload.map(|c| (c as usize).saturating_sub(32));
+        CSIType::MouseX10 { button: button as u8, x, y }
+    }
+
+    /// Encodes one value of an X10 mouse report, clamped to what fits in the protocol.
+    fn x10_char(n: usize) -> char {
+        char::from(32 + n.min(223) as u8)
+    }
+
+    /// Returns the parameters a sequence with `final_byte` uses when they are left out.
+    /// Empty for final bytes this crate doesn't parse.
+    pub fn default_params(final_byte: char) -> Vec<usize> {
+        match final_byte {
+            'A' | 'B' | 'C' | 'D' | 'E' | 'F' | 'G' | '`' | 'd' | 'L' | 'M' | 'S' | 'T' => vec![1],
+            'H' | 'f' | 's' => vec![1, 1],
+            'r' => vec![1, 0],
+            'J' | 'K' | 'm' => vec![0],
+            _ => vec![],
+        }
+    }
+
+    /// Returns true for an SGR that resets all attributes, i.e. `ESC[m` or `ESC[0m`.
+    pub fn is_sgr_reset(&self) -> bool {
+        matches!(self, CSIType::SGR(params) if params.is_empty() || params.as_slice() == [0])
+    }
+
     pub fn from_grapheme(gr: &str, args: Vec<String>) -> CSIType {
         if gr.len() != 1 {
-            CSIType::Unknown(format!("Unknown CSI command: {}", gr))
+            CSIType::unknown(format!("Unknown CSI command: {}", gr))
         } else {
             Self::from(gr, args)
         }
     }
 
+    /// `gr` is the final byte, preceded by any intermediate bytes (e.g. `"!p"`).
     pub fn from(gr: &str, _args: Vec<String>) -> CSIType {
         let mut args = _args.clone();
         let mut private = false;
-        if args.len() != 0 && args[0].starts_with("?") {
+        if !args.is_empty() && args[0].starts_with("?") {
             args[0].remove(0);
             private = true;
         }
-        // TODO: Totally rewrite this lol
-        let first_arg_result = {
-            if args.len() == 0 {
-                "not a number".parse::<usize>()
-            } else {
-                args[0].as_str().parse::<usize>()
-            }
-        };
-        let n;
-        let mut default = false;
-        if first_arg_result.is_ok() {
-            n = first_arg_result.unwrap();
-        } else {
-            n = 1;
-            default = true;
-        }
-
-        let m;
-        if args.len() > 1 {
-            let m_res = args[1].as_str().parse::<usize>();
-            if m_res.is_ok() {
-                m = m_res.unwrap();
-            } else {
-                m = 1;
-            }
-        } else {
-            m = 1;
+        if !args.is_empty() && args[0].starts_with("<") && (gr == "M" || gr == "m") {
+            args[0].remove(0);
+            let param = |i: usize| args.get(i).and_then(|arg| parse_param(arg)).unwrap_or(0);
+            return CSIType::MouseSgr { button: param(0), x: param(1), y: param(2), pressed: gr == "M" };
         }
+        // TODO: Totally rewrite this lol
+        let defaults = Self::default_params(gr.chars().last().unwrap_or_default());
+        let n = args.first().and_then(|arg| parse_param(arg))
+            .unwrap_or_else(|| defaults.first().copied().unwrap_or(1));
+        let m = args.get(1).and_then(|arg| parse_param(arg))
+            .unwrap_or_else(|| defaults.get(1).copied().unwrap_or(1));
 
         if !private {
             match gr {
@@ -320,59 +1153,126 @@ impl CSIType {
                 "D" => { CSIType::CUB(n) }
                 "E" => { CSIType::CNL(n) }
                 "F" => { CSIType::CPL(n) }
-                "G" => { CSIType::CHA(n) }
+                // HPA, same as CHA
+                "G" | "`" => { CSIType::CHA(n) }
                 "d" => { CSIType::CVA(n) }
                 "H" => { CSIType::CUP(n, m) }
-                "J" => { CSIType::ED( if default {0} else {n} ) }
-                "K" => { CSIType::EL( if default {0} else {n} ) }
+                "J" => { CSIType::ED(n) }
+                "K" => { CSIType::EL(n) }
                 "L" => { CSIType::IL(n) }
+                "M" => { CSIType::DL(n) }
                 "S" => { CSIType::SU(n) }
                 "T" => { CSIType::SD(n) }
-                "f" => { CSIType::CUP(n, m) }
+                "f" => { CSIType::HVP(n, m) }
                 "m" => {
-                    if default {
-                        CSIType::SGR(vec![0])
-                    } else {
-                        let mut sgr_args = Vec::<usize>::new();
-                        for i in 0..args.len() {
-                            let res = args[i].as_str().parse::<usize>();
-                            if res.is_ok() {
-                                sgr_args.push(res.unwrap());
-                            } else {
-                                sgr_args.push(0);
-                            }
-                        }
-                        CSIType::SGR(sgr_args)
+                    let mut sgr_args = SgrParams::new();
+                    for arg in &args {
+                        sgr::push_sgr_param(arg, &mut sgr_args);
+                    }
+                    if sgr_args.is_empty() {
+                        sgr_args.push(0);
                     }
+                    CSIType::SGR(sgr_args)
                 }
+                "r" if args.iter().all(String::is_empty) => { CSIType::DECSTBM(0, 0) }
                 "r" => { CSIType::DECSTBM(n, m) }
+                // Without parameters `s` saves the cursor instead of setting the margins
+                "s" if args.iter().all(String::is_empty) => { CSIType::SCOSC }
                 "s" => { CSIType::DECSLRM(n, m) }
-                _ => { CSIType::Unknown(format!("Unknown CSI command: {}", gr)) }
+                "u" => { CSIType::SCORC }
+                "n" if n == 5 => { CSIType::DSR(DsrRequest::Status) }
+                "n" if n == 6 => { CSIType::DSR(DsrRequest::CursorPosition) }
+                "t" => { CSIType::WindowOp(args.iter().map(|arg| parse_param(arg).unwrap_or(0)).collect()) }
+                "!p" => { CSIType::DECSTR }
+                "#p" | "#{" => { CSIType::PushSgr }
+                "#q" | "#}" => { CSIType::PopSgr }
+                " @" => { CSIType::SL(n) }
+                " A" => { CSIType::SR(n) }
+                " q" => { CSIType::DECSCUSR(args.first().and_then(|arg| parse_param(arg)).unwrap_or(0)) }
+                "\"q" => { CSIType::DECSCA(args.first().and_then(|arg| parse_param(arg)).unwrap_or(0)) }
+                _ => { CSIType::unknown(format!("Unknown CSI command: {}", gr)) }
             }
         } else {
-            match n {
-                25 => {
-                    match gr {
-                        "h" => { CSIType::DECTCEM(true) }
-                        "l" => { CSIType::DECTCEM(false) }
-                        _ => { CSIType::Unknown(format!("Unknown Private CSI command: {}{}", n, gr))}
+            match gr {
+                "h" | "l" => {
+                    let modes = args.iter()
+                        .map(|arg| DecPrivateMode::from(parse_param(arg).unwrap_or(0)))
+                        .collect::<Vec<DecPrivateMode>>();
+                    if modes == [DecPrivateMode::CursorVisible] {
+                        CSIType::DECTCEM(gr == "h")
+                    } else if gr == "h" {
+                        CSIType::DECSET(modes)
+                    } else {
+                        CSIType::DECRST(modes)
                     }
                 }
-                _ => { CSIType::Unknown(format!("Unknown Private CSI command: {}", n)) }
+                "$p" => { CSIType::DecRequestMode(n) }
+                "n" if n == 6 => { CSIType::DSR(DsrRequest::ExtendedCursorPosition) }
+                _ => { CSIType::unknown(format!("Unknown Private CSI command: {}{}", n, gr)) }
             }
         }
     }
 }
 
+/// Incremental parser over text fed with `new_text`. Cloning forks it, the copy starts with the
+/// same buffered input and parses independently from then on.
+#[derive(Clone)]
 pub struct AnsiEscaper {
     graphemes: Vec<String>,
+    /// Index of the first grapheme in `graphemes` not parsed yet.
+    pos: usize,
+    /// Trailing bytes of an incomplete UTF-8 sequence from the last `new_bytes`.
+    partial_char: Vec<u8>,
+    osc_config: OscConfig,
+    limits: ParseLimits,
+    max_text_chunk: usize,
+    /// Set by `flush`, lets the last grapheme out even though more input could still extend it.
+    flushed: bool,
 }
 
+/// Ends once the buffered input runs out. A sequence cut off at the end stays buffered, so
+/// iterating again after `new_text` picks up where it stopped. So does the last grapheme of
+/// trailing text until `flush` is called.
 impl Iterator for AnsiEscaper {
     type Item = AnsiType;
 
     fn next(&mut self) -> Option<Self::Item> {
-        Some(self.parse_next())
+        match self.parse_next() {
+            AnsiType::Incomplete => None,
+            ansi => Some(ansi),
+        }
+    }
+}
+
+/// Merges runs of consecutive `Text` events into one, see `AnsiEscaper::coalesced`.
+pub struct CoalesceText<I: Iterator<Item = AnsiType>> {
+    inner: Peekable<I>,
+}
+
+impl<I: Iterator<Item = AnsiType>> CoalesceText<I> {
+    pub fn new(inner: I) -> Self {
+        Self { inner: inner.peekable() }
+    }
+}
+
+impl<I: Iterator<Item = AnsiType>> Iterator for CoalesceText<I> {
+    type Item = AnsiType;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut text = match self.inner.next()? {
+            AnsiType::Text(text) => text,
+            other => return Some(other),
+        };
+        while let Some(AnsiType::Text(more)) = self.inner.next_if(|ansi| matches!(ansi, AnsiType::Text(_))) {
+            text += &more;
+        }
+        Some(AnsiType::Text(text))
+    }
+}
+
+impl Default for AnsiEscaper {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -380,29 +1280,114 @@ impl AnsiEscaper {
     pub const fn new() -> Self {
         Self {
             graphemes: vec![],
+            pos: 0,
+            partial_char: vec![],
+            osc_config: OscConfig::new(),
+            limits: ParseLimits::new(),
+            max_text_chunk: usize::MAX,
+            flushed: false,
         }
     }
 
+    /// Sets which OSC commands get parsed, see `OscConfig`.
+    pub fn set_osc_config(&mut self, config: OscConfig) {
+        self.osc_config = config;
+    }
+
+    /// Sets how many parameters a CSI or OSC may have, and how long they may be. A sequence going
+    /// over them comes out as `Unknown`, like with `escape_with_limits`.
+    pub fn set_limits(&mut self, limits: ParseLimits) {
+        self.limits = limits;
+    }
+
+    /// Appends `str` to the buffered input. A combining character at the start of `str` joins
+    /// the last buffered grapheme, which `parse_next` holds back for that until `flush` is called.
     pub fn new_text<S: AsRef<str>>(&mut self, str: S) {
-        let new_graphemes = str.as_ref().graphemes(false).collect::<Vec<&str>>();
-        for gr in new_graphemes {
+        self.flushed = false;
+        // Drop what was already parsed, so the buffer only grows with unparsed input
+        self.graphemes.drain(..self.pos);
+        self.pos = 0;
+        let mut text = self.graphemes.pop().unwrap_or_default();
+        text += str.as_ref();
+        for gr in text.graphemes(false) {
             self.graphemes.push(String::from(gr));
         }
     }
 
+    /// Limits how many bytes of text a single `Text` event holds, longer runs are split between
+    /// graphemes. A single grapheme longer than `max` still comes out whole. Unlimited by default.
+    pub fn set_max_text_chunk(&mut self, max: usize) {
+        self.max_text_chunk = max;
+    }
+
+    /// Same as `new_text`, but takes raw bytes. A multibyte character split across two calls is
+    /// buffered until it is complete, invalid bytes become U+FFFD.
+    pub fn new_bytes(&mut self, bytes: &[u8]) {
+        let text = decode_utf8_lossy(&mut self.partial_char, bytes);
+        self.new_text(text);
+    }
+
+    /// Returns how many bytes are buffered and not parsed yet, e.g. a sequence still waiting for
+    /// its final byte after `parse_next` returned `Incomplete`.
+    pub fn pending_bytes(&self) -> usize {
+        self.graphemes[self.pos..].iter().map(String::len).sum::<usize>() + self.partial_char.len()
+    }
+
+    /// Marks the end of the input so far, so `parse_next` returns the grapheme at the end of the
+    /// buffer instead of waiting for combining characters. Lasts until the next `new_text`.
+    pub fn flush(&mut self) {
+        self.flushed = true;
+    }
+
+    /// Throws away all buffered input, including any half parsed sequence.
+    /// Settings like the `OscConfig` are kept.
+    pub fn reset(&mut self) {
+        self.graphemes.clear();
+        self.pos = 0;
+        self.partial_char.clear();
+    }
+
     /// Returns the next ANSI code or next normal string, whichever is first.
     pub fn parse_next(&mut self) -> AnsiType {
         let mut string = String::new();
-        while let Some(gr) = self.graphemes.first() {
-            if gr == "\x1B" {
+        while let Some(gr) = self.graphemes.get(self.pos) {
+            if gr == "\x1B" || c1_introducer(gr).is_some() {
+                return if string.is_empty() {
+                    // Sequences cut off at the end of the buffer are parsed again from the start
+                    // once the rest arrives
+                    let start = self.pos;
+                    let ansi = self.parse_mouse_x10().unwrap_or_else(|| self.parse());
+                    if ansi == AnsiType::Incomplete {
+                        self.pos = start;
+                        return ansi;
+                    }
+                    self.osc_config.filter(ansi.with_raw(|| self.graphemes[start..self.pos].concat()))
+                } else {
+                    AnsiType::Text(string)
+                }
+            }
+            if let Some(control) = ControlChar::from_grapheme(gr) {
                 return if string.is_empty() {
-                    self.parse()
+                    if gr == "\r\n" {
+                        // Segmented as one grapheme, leave the line feed for the next call
+                        self.graphemes[self.pos] = String::from("\n");
+                    } else {
+                        self.pos += 1;
+                    }
+                    AnsiType::Control(control)
                 } else {
                     AnsiType::Text(string)
                 }
             }
+            if !string.is_empty() && string.len().saturating_add(gr.len()) > self.max_text_chunk {
+                return AnsiType::Text(string);
+            }
+            if self.pos + 1 == self.graphemes.len() && !self.flushed {
+                // The next `new_text` may still add combining characters to it
+                break;
+            }
             string += gr;
-            self.graphemes.remove(0);
+            self.pos += 1;
         }
 
         if string.is_empty() {
@@ -412,113 +1397,224 @@ impl AnsiEscaper {
         }
     }
 
-    fn next_grapheme(&mut self) -> Option<String> {
-        let mut ret = None;
-        if let Some(pog) = self.graphemes.first() {
-            ret = Some(pog.clone());
-            self.graphemes.remove(0);
-        }
-        ret
+    /// Parses everything buffered so far. A sequence cut off at the end stays buffered, and so does
+    /// the last grapheme of trailing text unless `flush` was called.
+    pub fn parse_all(&mut self) -> Vec<AnsiType> {
+        self.collect()
     }
 
-    fn parse(&mut self) -> AnsiType {
-        if self.graphemes.first() == Some(&String::from("\x1B"))  {
-            self.graphemes.remove(0);
+    /// Iterates like `AnsiEscaper` itself, but yields back to back `Text` events (e.g. the chunks
+    /// from `set_max_text_chunk`) as a single one.
+    pub fn coalesced(self) -> CoalesceText<Self> {
+        CoalesceText::new(self)
+    }
+
+    /// Returns the event the next `parse_next` call will return, without consuming it.
+    pub fn peek(&mut self) -> AnsiType {
+        let pos = self.pos;
+        let ansi = self.parse_next();
+        if self.graphemes.get(pos).map(String::as_str) == Some("\n") && ansi == AnsiType::Control(ControlChar::CarriageReturn) {
+            // Undo splitting `\r\n`
+            self.graphemes[pos] = String::from("\r\n");
         }
+        self.pos = pos;
+        ansi
+    }
 
-        let ansi_type = AnsiType::from(self.next_grapheme().unwrap().as_str());
-        match ansi_type {
-            AnsiType::Text(_) => {}
-            AnsiType::SS2 => {}
-            AnsiType::SS3 => {}
-            AnsiType::DCS => {}
-            AnsiType::CSI { .. } => {
-                // parameter bytes
-                let parameter_bytes = {
-                    let mut v = vec![];
-                    while let Some(g) = self.graphemes.first() {
-                        if g.is_ascii() {
-                            let chars = g.chars().collect::<Vec<char>>();
-                            if (0x30..=0x3F).contains(&(*chars.get(0).unwrap() as u32)) {
-                                v.push(chars.get(0).unwrap().clone());
-                                self.graphemes.remove(0);
-                            } else {
-                                break;
-                            }
-                        } else {
-                            break;
+    /// Skips the content of a DCS, SOS, PM or APC string up to and including the ST ending it.
+    fn parse_string(&mut self, ansi_type: AnsiType) -> AnsiType {
+        while let Some(gr) = self.graphemes.get(self.pos) {
+            match gr.as_str() {
+                "\u{9C}" => {
+                    self.pos += 1;
+                    return ansi_type;
+                }
+                "\x1B" => {
+                    return match self.graphemes.get(self.pos + 1).map(String::as_str) {
+                        Some("\\") => {
+                            self.pos += 2;
+                            ansi_type
                         }
+                        // Leave the new sequence for the next call
+                        Some(_) => AnsiType::unknown(format!("{}", AnsiError::UnterminatedString)),
+                        None => AnsiType::Incomplete,
                     }
-                    v
-                };
-                let mut parameters = vec![];
-                let mut tmp_param = String::new();
-                for bytes in parameter_bytes {
-                    if bytes != ';' {
-                        tmp_param.push(bytes.clone());
-                        continue;
+                }
+                _ => {
+                    self.pos += 1;
+                }
+            }
+        }
+        AnsiType::Incomplete
+    }
+
+    /// Reads the parameters, intermediates and final byte of a CSI by the same rules as `escape`.
+    /// ESC or a C1 introducer ends it early and is left for the next call, C0 controls and other
+    /// bytes not allowed in a CSI make it `Unknown` once the final byte arrives.
+    fn parse_csi(&mut self, ansi_type: AnsiType, intro_bytes: usize) -> AnsiType {
+        let mut parameters = String::new();
+        let mut intermediates = String::new();
+        // First byte not allowed where it was found and its offset, like in `escape`
+        let mut illegal: Option<(u8, usize)> = None;
+        let mut offset = intro_bytes;
+        // Parameters before the current one, and the current one's length
+        let (mut params, mut param_len) = (0, 0);
+        for (i, gr) in self.graphemes[self.pos..].iter().enumerate() {
+            if gr == "\x1B" || c1_introducer(gr).is_some() {
+                self.pos += i;
+                return AnsiType::unknown(format!("{}", AnsiError::Interrupted));
+            }
+            let byte = gr.as_bytes()[0];
+            let at = offset;
+            offset += gr.len();
+            if gr.len() > 1 || byte < 0x20 {
+                illegal.get_or_insert((byte, at));
+                continue;
+            }
+            let exceeded = match byte {
+                b';' if intermediates.is_empty() => {
+                    params += 1;
+                    param_len = 0;
+                    (params + 1 > self.limits.max_params).then_some(AnsiError::TooManyParameters { limit: self.limits.max_params })
+                }
+                0x30..=0x3F if intermediates.is_empty() => {
+                    param_len += 1;
+                    (param_len > self.limits.max_param_len).then_some(AnsiError::ParameterTooLong { limit: self.limits.max_param_len })
+                }
+                _ => None,
+            };
+            if let Some(error) = exceeded {
+                self.pos += i + 1;
+                return AnsiType::unknown(format!("{}", error));
+            }
+            match byte {
+                0x30..=0x3F if intermediates.is_empty() => parameters.push(char::from(byte)),
+                // Parameter bytes have to come before the intermediate bytes
+                0x30..=0x3F => {
+                    illegal.get_or_insert((byte, at));
+                }
+                0x20..=0x2F => intermediates.push(char::from(byte)),
+                _ => {
+                    self.pos += i + 1;
+                    if let Some((byte, offset)) = illegal {
+                        return AnsiType::unknown(format!("{}", AnsiError::IllegalParameterByte { byte, offset }));
                     }
-                    if tmp_param.len() == 0 {
-                        parameters.push(String::from("0"));
+                    intermediates.push(char::from(byte));
+                    // Empty parameters are kept, `CSIType::from` replaces them with the defaults
+                    let parameters = if parameters.is_empty() {
+                        vec![]
                     } else {
-                        parameters.push(tmp_param.clone());
-                        tmp_param.clear();
-                    }
+                        parameters.split(';').map(String::from).collect::<Vec<String>>()
+                    };
+                    return AnsiType::finish(&intermediates, ansi_type, parameters);
                 }
-                if tmp_param.len() != 0 {
-                    parameters.push(tmp_param.clone());
-                    tmp_param.clear();
+            }
+        }
+        AnsiType::Incomplete
+    }
+
+    /// `CSI M` is followed by three payload characters instead of parameters, so it gets read
+    /// separately. Waits with `Incomplete` until the whole report is buffered.
+    fn parse_mouse_x10(&mut self) -> Option<AnsiType> {
+        let intro_len = match self.graphemes.get(self.pos).map(String::as_str) {
+            Some("\u{9B}") => 1,
+            Some("\x1B") if self.graphemes.get(self.pos + 1).map(String::as_str) == Some("[") => 2,
+            _ => return None,
+        };
+        if self.graphemes.get(self.pos + intro_len).map(String::as_str) != Some("M") {
+            return None;
+        }
+        if self.graphemes.len() - self.pos < intro_len + 4 {
+            return Some(AnsiType::Incomplete);
+        }
+        let report = self.graphemes[self.pos + intro_len + 1..self.pos + intro_len + 4].iter()
+            .map(|gr| gr.chars().next().unwrap_or_default())
+            .collect::<Vec<char>>();
+        self.pos += intro_len + 4;
+        Some(AnsiType::CSI { kind: CSIType::mouse_x10([report[0], report[1], report[2]]) })
+    }
+
+    fn next_grapheme(&mut self) -> Option<String> {
+        let gr = self.graphemes.get(self.pos).cloned();
+        if gr.is_some() {
+            self.pos += 1;
+        }
+        gr
+    }
+
+    fn parse(&mut self) -> AnsiType {
+        // The character after ESC, the charset designations need it to tell the slot
+        let mut intro = String::new();
+        // Length of `ESC x` or the C1 form, for the offsets in errors
+        let intro_bytes;
+        let ansi_type = if let Some(c1) = self.graphemes.get(self.pos).and_then(|gr| c1_introducer(gr)) {
+            intro_bytes = self.graphemes[self.pos].len();
+            self.pos += 1;
+            c1
+        } else {
+            if self.graphemes.get(self.pos) == Some(&String::from("\x1B"))  {
+                self.pos += 1;
+            }
+            match self.next_grapheme() {
+                Some(gr) => {
+                    intro_bytes = 1 + gr.len();
+                    intro = gr;
+                    AnsiType::from(intro.as_str())
                 }
-                // intermediate bytes
-                let _ = {
-                    let mut v = vec![];
-                    while let Some(g) = self.graphemes.first() {
-                        if g.is_ascii() {
-                            let chars = g.chars().collect::<Vec<char>>();
-                            if chars.len() == 1 {
-                                if (0x20..=0x2F).contains(&(*chars.get(0).unwrap() as u32)) {
-                                    v.push(chars.get(0).unwrap().clone());
-                                    self.graphemes.remove(0);
-                                } else {
-                                    break;
-                                }
-                            } else {
-                                break;
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                    v
-                };
-                // final byte
-                let final_gr = self.graphemes.remove(0);
-                return AnsiType::finish(&final_gr, ansi_type, parameters);
+                None => return AnsiType::Incomplete,
             }
-            AnsiType::ST => {}
+        };
+        match ansi_type {
+            AnsiType::Text(_) => {}
+            AnsiType::Control(_) => {}
+            AnsiType::SS2 | AnsiType::SS3 => {
+                // Shifts only the one character following
+                if self.next_grapheme().is_some() {
+                    return ansi_type;
+                }
+            }
+            AnsiType::DCS | AnsiType::SOS | AnsiType::PM | AnsiType::APC => return self.parse_string(ansi_type),
+            AnsiType::CSI { .. } => return self.parse_csi(ansi_type, intro_bytes),
             AnsiType::OSC { .. } => {
-                if let Some(gr) = self.graphemes.first() {
+                if self.pos < self.graphemes.len() {
+                    let mut terminated = false;
                     let osc_raw = {
                         let mut v = vec![];
-                        while let Some(g) = self.graphemes.first() {
-                            if g.is_ascii() {
-                                let chars = g.chars().collect::<Vec<char>>();
-                                if [0x07, 0x9C].contains(&(*chars.get(0).unwrap() as u32)) {
-                                    self.graphemes.remove(0);
+                        // `;`-separated fields and bytes so far, for the limits
+                        let (mut fields, mut len) = (1, 0);
+                        while let Some(g) = self.graphemes.get(self.pos) {
+                            match g.as_str() {
+                                "\x07" | "\u{9C}" /* C1 ST */ => {
+                                    self.pos += 1;
+                                    terminated = true;
                                     break;
-                                } else if *chars.get(0).unwrap() == 0x1B as char {
-                                    if self.graphemes.get(1).is_some() && *self.graphemes.get(1).unwrap().chars().collect::<Vec<char>>().get(0).unwrap() == 0x5C as char {
-                                        self.graphemes.remove(0);
-                                        self.graphemes.remove(0);
-                                        break;
+                                }
+                                "\x1B" => {
+                                    match self.graphemes.get(self.pos + 1) {
+                                        Some(next) if next == "\\" => {
+                                            self.pos += 2;
+                                            terminated = true;
+                                        }
+                                        // Leave the new sequence for the next call
+                                        Some(_) => return AnsiType::unknown(format!("{}", AnsiError::Interrupted)),
+                                        None => {}
                                     }
-                                } else {
-                                    v.push(chars.get(0).unwrap().clone());
-                                    self.graphemes.remove(0);
+                                    break;
                                 }
-                            } else {
-                                break;
+                                _ => {}
+                            }
+                            self.pos += 1;
+                            len += g.len();
+                            if len > self.limits.max_string_len {
+                                return AnsiType::unknown(format!("{}", AnsiError::StringTooLong { limit: self.limits.max_string_len }));
                             }
+                            if g == ";" {
+                                fields += 1;
+                                if fields > self.limits.max_params {
+                                    return AnsiType::unknown(format!("{}", AnsiError::TooManyParameters { limit: self.limits.max_params }));
+                                }
+                            }
+                            v.extend(g.chars());
                         }
                         v
                     };
@@ -526,162 +1622,585 @@ impl AnsiEscaper {
                     let mut tmp_param = String::new();
                     for bytes in osc_raw {
                         if bytes != ';' {
-                            tmp_param.push(bytes.clone());
+                            tmp_param.push(bytes);
                             continue;
                         }
-                        if tmp_param.len() == 0 {
-                            parameters.push(String::from("0"));
-                        } else {
-                            parameters.push(tmp_param.clone());
-                            tmp_param.clear();
-                        }
+                        // Unlike CSI parameters, an empty one doesn't mean 0
+                        parameters.push(core::mem::take(&mut tmp_param));
                     }
-                    if tmp_param.len() != 0 {
-                        parameters.push(tmp_param.clone());
-                        tmp_param.clear();
-                    }
-                    if parameters.len() < 2 {
+                    parameters.push(tmp_param);
+                    if !terminated {
                         return AnsiType::Incomplete;
                     }
-                    match parameters[0].as_str() {
-                        "0" => return AnsiType::OSC { kind: OSCType::WindowTitle(parameters[1].clone()) },
-                        _ => return AnsiType::OSC { kind: OSCType::Unknown(parameters[1].clone()) },
-                    }
+                    return AnsiType::finish("\x07", ansi_type, parameters);
                 } else {
                     return AnsiType::Incomplete;
                 }
             }
-            AnsiType::RIS => {}
-            AnsiType::SOS => {}
-            AnsiType::PM => {}
-            AnsiType::APC => {}
+            AnsiType::RIS | AnsiType::KeypadApplication | AnsiType::KeypadNumeric
+            | AnsiType::Index | AnsiType::ReverseIndex | AnsiType::NextLine | AnsiType::ST => {
+                return ansi_type
+            }
             AnsiType::SETCHARSET => {
-                let _ = self.next_grapheme().unwrap();
-                return AnsiType::SETCHARSET
+                return match self.next_grapheme() {
+                    Some(gr) => AnsiType::designate_charset(&intro, &gr),
+                    None => AnsiType::Incomplete,
+                }
+            }
+            AnsiType::DesignateCharset { .. } => {}
+            AnsiType::DecAlignmentTest => {}
+            AnsiType::DecLineAttr(_) => {
+                return match self.next_grapheme() {
+                    Some(gr) => AnsiType::finish(&gr, ansi_type, vec![]),
+                    None => AnsiType::Incomplete,
+                }
             }
             AnsiType::Incomplete => {}
-            AnsiType::Unknown(_) => {}
+            // Skip the ESC and the character after it, like `escape` does once there is more input
+            AnsiType::Unknown { .. } if self.pos < self.graphemes.len() => return ansi_type,
+            AnsiType::Unknown { .. } => {}
         }
 
         AnsiType::Incomplete
     }
 }
 
+/// Decodes `bytes` as UTF-8 after any bytes left in `pending` by the previous call.
+/// An incomplete multibyte sequence at the end is kept in `pending`, invalid bytes become U+FFFD.
+pub(crate) fn decode_utf8_lossy(pending: &mut Vec<u8>, bytes: &[u8]) -> String {
+    let mut input = core::mem::take(pending);
+    input.extend_from_slice(bytes);
+
+    let mut string = String::new();
+    let mut rest = input.as_slice();
+    loop {
+        match core::str::from_utf8(rest) {
+            Ok(s) => {
+                string += s;
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                // SAFETY: from_utf8 just checked this part
+                string += unsafe { core::str::from_utf8_unchecked(valid) };
+                match e.error_len() {
+                    Some(len) => {
+                        string.push(char::REPLACEMENT_CHARACTER);
+                        rest = &after[len..];
+                    }
+                    None => {
+                        // Sequence is cut off, wait for the rest of it
+                        pending.extend_from_slice(after);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    string
+}
+
 pub trait ToAnsi {
     fn to_ansi(&self) -> AnsiEscaper;
 }
 
-impl ToAnsi for &str {
+/// Covers `&str`, `String`, `&String` and anything else string-like.
+impl<S: AsRef<str> + ?Sized> ToAnsi for S {
     fn to_ansi(&self) -> AnsiEscaper {
         let mut escaper = AnsiEscaper::new();
         escaper.new_text(self);
+        escaper.flush();
         escaper
     }
 }
 
-pub fn read_until_escape_char<S: AsRef<str>>(s: S) -> String {
-    let graphemes = s.as_ref().graphemes(false).collect::<Vec<&str>>();
+/// Returns how many terminal columns `s` occupies.
+///
+/// Measured per grapheme, so combining marks add nothing and an emoji ZWJ sequence counts as
+/// a single wide character instead of the sum of its parts.
+#[cfg(feature = "unicode-width")]
+pub fn text_width(s: &str) -> usize {
+    s.graphemes(true).map(|gr| UnicodeWidthStr::width(gr).min(2)).sum()
+}
 
+/// Reads text until the first escape or C0 control character.
+/// Returns the text and its length in bytes, which is the whole string if there is no escape.
+pub fn read_until_escape_char<S: AsRef<str>>(s: S) -> (String, usize) {
     let mut string = String::new();
 
-    for grapheme in graphemes {
-        if grapheme == "\x1B" {
+    for grapheme in s.as_ref().graphemes(false) {
+        if grapheme == "\x1B" || c1_introducer(grapheme).is_some() || ControlChar::from_grapheme(grapheme).is_some() {
             break;
         }
         string += grapheme;
     }
 
-    string
+    let length = string.len();
+    (string, length)
+}
+
+/// Parses a numeric parameter, `None` if it is empty or not a number. Numbers too big for a
+/// `usize` saturate to `usize::MAX` instead of being treated as missing.
+pub(crate) fn parse_param(arg: &str) -> Option<usize> {
+    if arg.is_empty() || !arg.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(arg.parse::<usize>().unwrap_or(usize::MAX))
+}
+
+/// Number of decimal digits in `n`.
+fn digits(n: usize) -> usize {
+    n.checked_ilog10().unwrap_or(0) as usize + 1
+}
+
+/// Length of `;`-joined parameters with the given lengths.
+fn joined_len<I: Iterator<Item = usize>>(lens: I) -> usize {
+    let mut count = 0usize;
+    let total = lens.inspect(|_| count += 1).sum::<usize>();
+    total + count.saturating_sub(1)
+}
+
+/// Maps the 8-bit C1 forms of the introducers (e.g. 0x9B for `ESC [`) to their type.
+fn c1_introducer(gr: &str) -> Option<AnsiType> {
+    match gr {
+        "\u{90}" => Some(AnsiType::DCS),
+        "\u{9B}" => Some(AnsiType::CSI { kind: CSIType::unknown(String::new()) }),
+        "\u{9C}" => Some(AnsiType::ST),
+        "\u{9D}" => Some(AnsiType::OSC { kind: OSCType::unknown(String::new()) }),
+        _ => None,
+    }
+}
+
+/// Upper bounds on how much parameter data a single CSI or OSC may accumulate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParseLimits {
+    /// Maximum number of parameters in one CSI, or `;`-separated fields in one OSC.
+    pub max_params: usize,
+    /// Maximum length in bytes of a single CSI parameter.
+    pub max_param_len: usize,
+    /// Maximum length in bytes of an OSC string. Separate from `max_param_len`, as titles and
+    /// OSC 52 clipboard writes can legitimately be long.
+    pub max_string_len: usize,
+}
+
+impl Default for ParseLimits {
+    /// 16 parameters, 2048 bytes per CSI parameter, 1 MiB per OSC string.
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParseLimits {
+    /// 16 parameters, 2048 bytes per CSI parameter, 1 MiB per OSC string.
+    pub const fn new() -> Self {
+        Self {
+            max_params: 16,
+            max_param_len: 2048,
+            max_string_len: 1 << 20,
+        }
+    }
 }
 
 /// Escapes a given string, and returns the first found ANSI code and how many characters it occupies in a tuple.
+///
+/// The length is only 0 for `Incomplete`. Everything else, `Unknown` included, covers at least
+/// the bytes that were read, so slicing them off and calling `escape` again always makes progress.
+/// A malformed sequence is skipped up to where it went wrong, e.g. up to the next ESC if it was
+/// interrupted by one.
 pub fn escape<S: AsRef<str>>(s: S) -> (AnsiType, usize) {
-    let graphemes = s.as_ref().graphemes(false).collect::<Vec<&str>>();
+    escape_with_limits(s, &ParseLimits::default())
+}
+
+/// Same as [`escape`] on `&s[byte_offset..]`, for walking through `s` by adding up the
+/// returned lengths. Only the part making up the returned event gets segmented, so parsing a whole
+/// string this way takes linear time.
+///
+/// Panics if `byte_offset` is past the end of `s` or not on a char boundary, like slicing does.
+pub fn escape_at(s: &str, byte_offset: usize) -> (AnsiType, usize) {
+    escape(&s[byte_offset..])
+}
+
+/// Same as [`escape`], but gives up with `Unknown` once a CSI or OSC exceeds `limits`.
+/// The returned length covers everything consumed up to that point.
+pub fn escape_with_limits<S: AsRef<str>>(s: S, limits: &ParseLimits) -> (AnsiType, usize) {
+    let s = s.as_ref();
+    match escape_sequence(s, limits) {
+        Ok((ansi, length)) => (ansi.with_raw(|| String::from(&s[..length])), length),
+        Err((error, length)) => {
+            (AnsiType::Unknown { raw: String::from(&s[..length]), reason: format!("{}", error) }, length)
+        }
+    }
+}
 
-    if graphemes.len() == 0 {
-        return (AnsiType::Incomplete,0);
+/// Same as [`escape`], but only parses the OSC commands enabled in `config`.
+pub fn escape_with_config<S: AsRef<str>>(s: S, config: &OscConfig) -> (AnsiType, usize) {
+    let (ansi, length) = escape(s);
+    (config.filter(ansi), length)
+}
+
+/// Same as [`escape`], but returns why a sequence couldn't be parsed instead of `Unknown`, and
+/// `AnsiError::Incomplete` instead of `Incomplete`.
+pub fn try_escape<S: AsRef<str>>(s: S) -> Result<(AnsiType, usize), AnsiError> {
+    let s = s.as_ref();
+    match escape_sequence(s, &ParseLimits::default()) {
+        Ok((AnsiType::Incomplete, _)) => Err(AnsiError::Incomplete),
+        Ok((ansi, length)) => Ok((ansi.with_raw(|| String::from(&s[..length])), length)),
+        Err((error, _)) => Err(error),
+    }
+}
+
+/// Why [`try_escape`] couldn't parse a sequence.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnsiError {
+    /// The input ends before the sequence does.
+    Incomplete,
+    /// `byte` isn't allowed at this point of the sequence, `offset` is its position in the input.
+    /// Reported once the sequence ends, for the first illegal byte in it.
+    IllegalParameterByte { byte: u8, offset: usize },
+    /// The sequence has more parameters than `ParseLimits::max_params`.
+    TooManyParameters { limit: usize },
+    /// A parameter is longer than `ParseLimits::max_param_len` bytes.
+    ParameterTooLong { limit: usize },
+    /// An OSC string is longer than `ParseLimits::max_string_len` bytes.
+    StringTooLong { limit: usize },
+    /// An OSC, DCS, SOS, PM or APC string was cut off by an escape sequence other than ST.
+    UnterminatedString,
+    /// A new escape sequence started before the current one was complete.
+    Interrupted,
+    /// The character after ESC doesn't start any known sequence.
+    UnknownEscape(String),
+}
+
+impl Display for AnsiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            AnsiError::Incomplete => f.write_str("Incomplete escape sequence"),
+            AnsiError::IllegalParameterByte { byte, offset } => {
+                write!(f, "Illegal byte {:#04x} found in escape sequence at offset {}", byte, offset)
+            }
+            AnsiError::TooManyParameters { limit } => write!(f, "Too many parameters, limit is {}", limit),
+            AnsiError::ParameterTooLong { limit } => write!(f, "Parameter too long, limit is {} bytes", limit),
+            AnsiError::StringTooLong { limit } => write!(f, "String too long, limit is {} bytes", limit),
+            AnsiError::UnterminatedString => f.write_str("Unterminated string"),
+            AnsiError::Interrupted => f.write_str("Escape sequence interrupted by another one"),
+            AnsiError::UnknownEscape(s) => f.write_str(s),
+        }
     }
-    if graphemes.len() < 2 || graphemes[0] != "\x1B" /* Escape char */ {
-        let string = read_until_escape_char(s);
-        let length = string.len();
-        return (AnsiType::Text(string), length);
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AnsiError {}
+
+fn escape_sequence(s: &str, limits: &ParseLimits) -> Result<(AnsiType, usize), (AnsiError, usize)> {
+    // Only the introducer is segmented up front, the rest is read one grapheme at a time so the
+    // limits apply while scanning and parsing a long string piece by piece stays linear
+    let mut head = s.graphemes(false);
+    let first = match head.next() {
+        Some(gr) => gr,
+        None => return Ok((AnsiType::Incomplete, 0)),
+    };
+    let t;
+    if let Some(control) = ControlChar::from_grapheme(first) {
+        return Ok((AnsiType::Control(control), 1));
+    } else if let Some(c1) = c1_introducer(first) {
+        t = c1;
+    } else if first != "\x1B" /* Escape char */ {
+        let (string, length) = read_until_escape_char(s);
+        return Ok((AnsiType::Text(string), length));
         //return (AnsiType::Unknown(String::from("First character not escape char")),1);
+    } else {
+        t = match head.next() {
+            None => return Ok((AnsiType::Incomplete, 0)),
+            // Only skip the first ESC, the second one starts the next sequence
+            Some("\x1B") => return Err((AnsiError::Interrupted, 1)),
+            Some(gr) => AnsiType::from(gr),
+        };
     }
-    if graphemes[1] == ">" {
-        return (AnsiType::Unknown(String::from("I do not know how to handle '>'")),2);
+    // The returned lengths are in bytes, and C1 introducers take up two of them
+    let intro_bytes = s.len() - head.as_str().len();
+    let body = head.as_str();
+
+    if let AnsiType::KeypadApplication | AnsiType::KeypadNumeric | AnsiType::RIS | AnsiType::ST
+        | AnsiType::Index | AnsiType::ReverseIndex | AnsiType::NextLine = t {
+        // Complete after the introducer
+        return Ok((t, intro_bytes));
     }
-    if graphemes.len() < 3 {
-        return (AnsiType::Incomplete, 0);
+    let next = match body.graphemes(false).next() {
+        Some(gr) => gr,
+        None => return Ok((AnsiType::Incomplete, 0)),
+    };
+    if let AnsiType::Unknown { reason, .. } = t {
+        return Err((AnsiError::UnknownEscape(reason), intro_bytes));
     }
 
-    let t = AnsiType::from(graphemes[1]);
+    if let AnsiType::CSI { .. } = t {
+        if next == "M" {
+            // X10 mouse report, three payload characters instead of parameters
+            let mut payload = body[1..].char_indices();
+            return Ok(match (payload.next(), payload.next(), payload.next()) {
+                (Some((_, button)), Some((_, x)), Some((i, y))) => {
+                    (AnsiType::CSI { kind: CSIType::mouse_x10([button, x, y]) }, intro_bytes + 1 + i + y.len_utf8())
+                }
+                _ => (AnsiType::Incomplete, 0),
+            });
+        }
+        // Fast path for the reset sequences `ESC[m` and `ESC[0m`, by far the most common ones
+        let mut ahead = body.graphemes(false);
+        match (ahead.next(), ahead.next()) {
+            (Some("m"), _) => return Ok((AnsiType::CSI { kind: CSIType::SGR(SgrParams::from_iter([0])) }, intro_bytes + 1)),
+            (Some("0"), Some("m")) => return Ok((AnsiType::CSI { kind: CSIType::SGR(SgrParams::from_iter([0])) }, intro_bytes + 2)),
+            _ => {}
+        }
+        // TODO: Handle special (OEM) CSI codes
+        /*if byte_arr[2] != '?' as u8 {
+            special = true;
+        }*/
+    }
 
-    let char_ranges = AnsiType::valid_char_ranges(&t);
-    //let mut special = false;
     match t {
-        AnsiType::CSI { .. } => {
-            // TODO: Handle special (OEM) CSI codes
-            /*if byte_arr[2] != '?' as u8 {
-                special = true;
-            }*/
-        }
-        AnsiType::Unknown(e_str) => {return (AnsiType::Unknown(e_str),2)}
+        // Only has a 7 bit form, so the slot is always the grapheme after ESC
+        AnsiType::SETCHARSET => return Ok((AnsiType::designate_charset(&s[1..intro_bytes], next), intro_bytes + next.len())),
+        AnsiType::OSC { .. } => return escape_osc(body, intro_bytes, t, limits),
+        AnsiType::DCS | AnsiType::SOS | AnsiType::PM | AnsiType::APC => return escape_string(body, intro_bytes, t),
         _ => {}
     }
 
-    let valid_char_ranges = char_ranges.0;
-    let end_char_range= 1..0;
+    let valid_char_ranges = AnsiType::valid_char_ranges(&t).0;
+    let end_char_range= 0..0;
 
     let mut arguments: Vec<String> = Vec::new();
     let mut curr_arg = String::new();
-    let mut i = 0;
-    let mut escaping = false;
-    let mut ansi_string = String::new();
-
-    for grapheme in graphemes {
-        if i < 2 { i += 1; continue; }
-        i += 1;
-        if grapheme.len() > 1 {
-            return (AnsiType::Unknown(String::new()), 0);
-        }
-
-        if grapheme == "\x1b" || escaping {
-            escaping = true;
-            ansi_string += grapheme;
-            let res = escape(ansi_string.clone());
-            if res.1 > 0 {
-                match res.0 {
-                    AnsiType::ST => {
-                        return (AnsiType::finish("\x07", t, arguments),i);
-                    }
-                    _ => {
-                    }
-                }
-                escaping = false;
-            }
+    let mut length = intro_bytes;
+    // Intermediate bytes between the parameters and the final byte, e.g. the `!` in `CSI ! p`
+    let mut intermediates = String::new();
+    // First byte not allowed where it was found and its offset, reported when the sequence ends
+    let mut illegal: Option<(u8, usize)> = None;
+
+    for grapheme in body.graphemes(false) {
+        let offset = length;
+        length += grapheme.len();
+        if grapheme == "\x1B" || c1_introducer(grapheme).is_some() {
+            // Only strings end with ST, anywhere else this starts the next sequence
+            return Err((AnsiError::Interrupted, offset));
+        }
+        let byte = grapheme.as_bytes()[0];
+        if grapheme.len() > 1 || byte < 0x20 {
+            illegal.get_or_insert((byte, offset));
+            continue;
+        }
+
+        if !intermediates.is_empty() && (0x30..0x40).contains(&byte) {
+            // Parameter bytes have to come before the intermediate bytes
+            illegal.get_or_insert((byte, offset));
             continue;
         }
 
         if grapheme == ";" {
+            // The `;` starts another parameter after the current one
+            if arguments.len() + 2 > limits.max_params {
+                return Err((AnsiError::TooManyParameters { limit: limits.max_params }, length));
+            }
             arguments.push(curr_arg.clone());
             curr_arg.clear();
             continue;
         }
 
-        let ch = grapheme.as_bytes()[0] as char;
+        let ch = byte as char;
+
+        if let AnsiType::CSI { .. } = t {
+            if (0x20..0x30).contains(&u32::from(ch)) {
+                intermediates.push(ch);
+                continue;
+            }
+        }
 
         if valid_char_ranges.contains(&u32::from(ch)) {
+            if curr_arg.len() >= limits.max_param_len {
+                return Err((AnsiError::ParameterTooLong { limit: limits.max_param_len }, length));
+            }
             curr_arg.push(ch);
         } else if end_char_range.contains(&u32::from(ch)) {
             arguments.push(curr_arg.clone());
-            return (AnsiType::finish(grapheme, t, arguments), i);
+            return Ok((AnsiType::finish(grapheme, t, arguments), length));
             // Get CSI Type
+        } else if let Some((byte, offset)) = illegal {
+            return Err((AnsiError::IllegalParameterByte { byte, offset }, length));
         } else {
             arguments.push(curr_arg.clone());
-            return (AnsiType::finish(grapheme, t, arguments), i);
+            intermediates += grapheme;
+            return Ok((AnsiType::finish(&intermediates, t, arguments), length));
             //return (AnsiType::Unknown(format!("Illegal character {:?} found in escape sequence", ch)), i);
         }
     }
 
-    (AnsiType::Incomplete, 0)
+    Ok((AnsiType::Incomplete, 0))
+}
+
+/// Scans the string part of an OSC, `body` starts after the introducer. Unlike CSI parameters
+/// the payload isn't limited, e.g. an OSC 52 clipboard write can legitimately be long.
+fn escape_osc(body: &str, intro_bytes: usize, t: AnsiType, limits: &ParseLimits) -> Result<(AnsiType, usize), (AnsiError, usize)> {
+    let mut arguments: Vec<String> = Vec::new();
+    let mut curr_arg = String::new();
+    let mut length = intro_bytes;
+    let mut iter = body.graphemes(false).peekable();
+
+    while let Some(grapheme) = iter.next() {
+        length += grapheme.len();
+        match grapheme {
+            "\x07" | "\u{9C}" => {
+                arguments.push(curr_arg);
+                return Ok((AnsiType::finish("\x07", t, arguments), length));
+            }
+            "\x1B" => {
+                return match iter.peek() {
+                    Some(&"\\") => {
+                        arguments.push(curr_arg);
+                        Ok((AnsiType::finish("\x07", t, arguments), length + 1))
+                    }
+                    Some(_) => Err((AnsiError::UnterminatedString, length - 1)),
+                    None => Ok((AnsiType::Incomplete, 0)),
+                }
+            }
+            _ if length - intro_bytes > limits.max_string_len => {
+                return Err((AnsiError::StringTooLong { limit: limits.max_string_len }, length));
+            }
+            ";" if arguments.len() + 2 > limits.max_params => {
+                return Err((AnsiError::TooManyParameters { limit: limits.max_params }, length));
+            }
+            ";" => arguments.push(core::mem::take(&mut curr_arg)),
+            _ => curr_arg += grapheme,
+        }
+    }
+
+    Ok((AnsiType::Incomplete, 0))
+}
+
+/// Skips over the string of a DCS, SOS, PM or APC up to the ST ending it, `body` starts after
+/// the introducer. The string itself isn't interpreted.
+fn escape_string(body: &str, intro_bytes: usize, t: AnsiType) -> Result<(AnsiType, usize), (AnsiError, usize)> {
+    let mut length = intro_bytes;
+    let mut iter = body.graphemes(false).peekable();
+
+    while let Some(grapheme) = iter.next() {
+        length += grapheme.len();
+        match grapheme {
+            "\u{9C}" => return Ok((t, length)),
+            "\x1B" => {
+                return match iter.peek() {
+                    Some(&"\\") => Ok((t, length + 1)),
+                    Some(_) => Err((AnsiError::UnterminatedString, length - 1)),
+                    None => Ok((AnsiType::Incomplete, 0)),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok((AnsiType::Incomplete, 0))
+}
+
+/// Builds the DECRPM reply to a `CSIType::DecRequestMode` query. `state` is 0 if the mode isn't
+/// recognized, 1 / 2 if it is set / reset, 3 / 4 if it is permanently set / reset.
+pub fn decrpm(mode: usize, state: usize) -> String {
+    format!("\x1B[?{};{}$y", mode, state)
+}
+
+/// The reply to a `DsrRequest::Status` query, "no malfunction".
+pub fn dsr_ok_response() -> String {
+    String::from("\x1B[0n")
+}
+
+/// Returns `s` with all escape sequences removed. Control characters like `\n` are kept,
+/// an incomplete sequence at the end is dropped.
+pub fn strip<S: AsRef<str>>(s: S) -> String {
+    let mut string = String::new();
+    let mut rest = s.as_ref();
+    while !rest.is_empty() {
+        let (ansi, length) = escape(rest);
+        if length == 0 {
+            break;
+        }
+        match ansi {
+            AnsiType::Text(text) => string += &text,
+            AnsiType::Control(c) => string.push(c.as_char()),
+            _ => {}
+        }
+        rest = &rest[length..];
+    }
+    string
+}
+
+/// Returns how many columns `s` occupies on screen, ignoring escape sequences and control
+/// characters. Wide characters count as 2 with the `unicode-width` feature, otherwise every
+/// grapheme counts as 1.
+pub fn visible_length<S: AsRef<str>>(s: S) -> usize {
+    let stripped = strip(s);
+    let visible = stripped.graphemes(true).filter(|gr| ControlChar::from_grapheme(gr).is_none());
+
+    #[cfg(feature = "unicode-width")]
+    return visible.map(text_width).sum();
+
+    #[cfg(not(feature = "unicode-width"))]
+    return visible.count();
+}
+
+/// Same as [`escape`], but on raw bytes. Lone 8-bit C1 bytes (e.g. 0x9B for CSI) are accepted
+/// as introducers, other invalid UTF-8 is read as U+FFFD. The returned length is in input bytes.
+pub fn escape_bytes(bytes: &[u8]) -> (AnsiType, usize) {
+    let mut s = String::new();
+    // (decoded length, input length) after each char that didn't decode byte for byte
+    let mut shifts = vec![(0, 0)];
+    let mut rest = bytes;
+    // Only decode as much as the first event needs, doubling it until the event ends before it
+    let mut wanted = 64;
+    loop {
+        let mut cut_off = false;
+        while !rest.is_empty() && s.len() < wanted {
+            let chunk = &rest[..rest.len().min(wanted - s.len())];
+            let valid = match core::str::from_utf8(chunk) {
+                Ok(valid) => valid,
+                // SAFETY: from_utf8 just checked this part
+                Err(e) => unsafe { core::str::from_utf8_unchecked(&chunk[..e.valid_up_to()]) },
+            };
+            if !valid.is_empty() {
+                s += valid;
+                rest = &rest[valid.len()..];
+                continue;
+            }
+            // Invalid, or a char cut off by the end of the chunk. Decodes just that one, which
+            // takes 4 bytes at most
+            let window = &rest[..rest.len().min(4)];
+            let (ch, used) = match core::str::from_utf8(window) {
+                Ok(valid) => (valid.chars().next(), None),
+                Err(e) if e.valid_up_to() > 0 => {
+                    // SAFETY: from_utf8 just checked this part
+                    let valid = unsafe { core::str::from_utf8_unchecked(&window[..e.valid_up_to()]) };
+                    (valid.chars().next(), None)
+                }
+                Err(e) => match e.error_len() {
+                    // Cut off multibyte sequence, leave it for the next call
+                    None => (None, None),
+                    Some(_) if (0x80..=0x9F).contains(&rest[0]) => (Some(char::from(rest[0])), Some(1)),
+                    Some(len) => (Some(char::REPLACEMENT_CHARACTER), Some(len)),
+                },
+            };
+            let Some(ch) = ch else {
+                cut_off = true;
+                break;
+            };
+            s.push(ch);
+            rest = &rest[used.unwrap_or(ch.len_utf8())..];
+            if used.is_some() {
+                shifts.push((s.len(), bytes.len() - rest.len()));
+            }
+        }
+
+        let (ansi, len) = escape(&s);
+        if rest.is_empty() || cut_off || (ansi != AnsiType::Incomplete && len < s.len()) {
+            // Lengths always end on a char boundary, so at or after the last shift before them
+            let (decoded, input) = shifts.iter().rev().find(|(decoded, _)| *decoded <= len).copied().unwrap_or_default();
+            return (ansi, input + (len - decoded));
+        }
+        wanted = wanted.saturating_mul(2);
+    }
 }
\ No newline at end of file
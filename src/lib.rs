@@ -5,15 +5,35 @@ extern crate std;
 
 extern crate alloc;
 
+#[cfg(feature = "term")]
 pub mod term;
 pub mod ansi_escaper;
+pub mod sgr;
+pub mod screen;
 
+// The tests driving a `Term` need the `term` feature, the parser ones are also run without it:
+// `cargo test --no-default-features --features std`
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "term")]
+    use alloc::boxed::Box;
+    #[cfg(feature = "term")]
+    use alloc::rc::Rc;
+    #[cfg(feature = "term")]
+    use core::cell::RefCell;
+    use alloc::format;
+    use alloc::string::String;
     use alloc::vec;
+    use alloc::vec::Vec;
     use std::println;
     use crate::ansi_escaper;
-    use crate::ansi_escaper::{AnsiType, CSIType};
+    use crate::ansi_escaper::{AnsiError, AnsiEscaper, CoalesceText, AnsiType, Charset, CharsetSlot, CSIType, ControlChar, CursorStyle, DecLineAttr, DecPrivateMode, DsrRequest, OSCType, OscConfig, ParseLimits, ToAnsi};
+    use crate::screen::ScreenState;
+    use crate::sgr::{parse_sgr, styled_spans, Color, SgrParam, SgrParams, SgrStyle, UnderlineStyle};
+    #[cfg(feature = "term")]
+    use crate::ansi_escaper::{dec_special_to_unicode, EraseRegion};
+    #[cfg(feature = "term")]
+    use crate::term::{Term, TermInterface, TermStatic, WriteOutcome};
 
     #[test]
     fn incomplete_ansi() {
@@ -24,9 +44,1865 @@ mod tests {
 
     #[test]
     fn simple_color() {
-        let incomplete = ansi_escaper::escape("\x1B[1;1H hello");
+        let incomplete = ansi_escaper::escape("\x1B[0m hello");
         println!();
-        assert_eq!(incomplete.0, AnsiType::CSI { kind: CSIType::SGR(0, vec![0]) });
-        assert_eq!(incomplete.1, 0);
+        assert_eq!(incomplete.0, AnsiType::sgr(&[0]));
+        assert_eq!(incomplete.1, 4);
+    }
+
+    #[test]
+    fn text_accessor() {
+        let text = AnsiType::Text(String::from("hi"));
+        assert_eq!(text.text(), Some("hi"));
+        assert_eq!(AnsiType::SS2.text(), None);
+        assert_eq!(text.into_text(), Some(String::from("hi")));
+        assert_eq!(AnsiType::SS2.into_text(), None);
+    }
+
+    #[test]
+    fn keypad_mode() {
+        assert_eq!(ansi_escaper::escape("\x1B="), (AnsiType::KeypadApplication, 2));
+        assert_eq!(ansi_escaper::escape("\x1B>"), (AnsiType::KeypadNumeric, 2));
+        assert_eq!("\x1B>".to_ansi().parse_next(), AnsiType::KeypadNumeric);
+    }
+
+    #[test]
+    fn parse_limits() {
+        let limits = ParseLimits::default();
+        let mut many_params = String::from("\x1B[");
+        many_params.push_str(&";".repeat(10_000));
+        many_params.push('m');
+        let (ansi, len) = ansi_escaper::escape_with_limits(&many_params, &limits);
+        assert!(matches!(ansi, AnsiType::Unknown { .. }));
+        assert_eq!(len, 2 + limits.max_params);
+
+        let mut long_param = String::from("\x1B[");
+        long_param.push_str(&"1".repeat(10_000));
+        long_param.push('m');
+        let (ansi, len) = ansi_escaper::escape(&long_param);
+        assert!(matches!(ansi, AnsiType::Unknown { .. }));
+        assert_eq!(len, 2 + limits.max_param_len + 1);
+
+        // The escaper stops at the same point, and parses what follows as text
+        for input in [&many_params, &long_param] {
+            let parsed = input.to_ansi().parse_all();
+            assert!(matches!(&parsed[0], AnsiType::Unknown { .. }), "{:?}", parsed[0]);
+            assert!(matches!(&parsed[1], AnsiType::Text(_)));
+        }
+        let mut escaper = AnsiEscaper::new();
+        escaper.set_limits(ParseLimits { max_params: 2, max_param_len: 3, ..ParseLimits::new() });
+        escaper.new_text("\x1B[1;2;3m\x1B[1234m\x1B[1;234m");
+        let parsed = escaper.parse_all();
+        let reason = |ansi: &AnsiType| match ansi {
+            AnsiType::Unknown { reason, .. } => reason.clone(),
+            _ => String::new(),
+        };
+        assert_eq!(reason(&parsed[0]), format!("{}", AnsiError::TooManyParameters { limit: 2 }));
+        assert_eq!(parsed[1], AnsiType::Text(String::from("3m")));
+        assert_eq!(reason(&parsed[2]), format!("{}", AnsiError::ParameterTooLong { limit: 3 }));
+        assert_eq!(parsed[3..], [AnsiType::Text(String::from("m")), AnsiType::sgr(&[1, 234])]);
+
+        // OSC payloads get their own, larger length limit
+        let data = "A".repeat(3000);
+        let clipboard = format!("\x1B]52;c;{}\x07", data);
+        let expected = AnsiType::OSC { kind: OSCType::Clipboard { selection: String::from("c"), data } };
+        assert_eq!(ansi_escaper::escape(&clipboard), (expected.clone(), clipboard.len()));
+        assert_eq!(clipboard.to_ansi().parse_all(), [expected]);
+        let link = format!("\x1B]8;{};https://example.com\x07", "a=b:".repeat(20).trim_end_matches(':'));
+        assert!(matches!(ansi_escaper::escape(&link).0, AnsiType::OSC { kind: OSCType::Hyperlink { .. } }));
+
+        // but the number of fields is limited like CSI parameters, in both parsers
+        let many_fields = format!("\x1B]0{}x\x07", ";".repeat(1_000_000));
+        let (ansi, len) = ansi_escaper::escape_with_limits(&many_fields, &limits);
+        assert_eq!(reason(&ansi), format!("{}", AnsiError::TooManyParameters { limit: limits.max_params }));
+        assert_eq!(len, 3 + limits.max_params);
+        let parsed = many_fields.to_ansi().parse_all();
+        assert_eq!(reason(&parsed[0]), format!("{}", AnsiError::TooManyParameters { limit: limits.max_params }));
+        assert_eq!(parsed[0].byte_len(), len);
+
+        let small = ParseLimits { max_params: 3, max_string_len: 8, ..ParseLimits::new() };
+        let long_title = "\x1B]0;123456789\x07";
+        let (ansi, len) = ansi_escaper::escape_with_limits(long_title, &small);
+        assert_eq!((reason(&ansi), len), (format!("{}", AnsiError::StringTooLong { limit: 8 }), 2 + 9));
+        let mut escaper = AnsiEscaper::new();
+        escaper.set_limits(small);
+        escaper.new_text(long_title);
+        escaper.new_text("\x1B]0;1;2;3\x07\x1B]0;12345\x1B\\");
+        escaper.flush();
+        let parsed = escaper.parse_all();
+        assert_eq!(reason(&parsed[0]), format!("{}", AnsiError::StringTooLong { limit: 8 }));
+        assert_eq!(parsed[1], AnsiType::Text(String::from("89")));
+        assert_eq!(parsed[2], AnsiType::Control(ControlChar::Bell));
+        assert_eq!(reason(&parsed[3]), format!("{}", AnsiError::TooManyParameters { limit: 3 }));
+        assert_eq!(parsed[4..], [
+            AnsiType::Text(String::from("3")),
+            AnsiType::Control(ControlChar::Bell),
+            AnsiType::set_title("12345"),
+        ]);
+    }
+
+    #[test]
+    fn empty_window_title() {
+        let empty_title = AnsiType::OSC { kind: OSCType::WindowTitle(String::new()) };
+        assert_eq!(ansi_escaper::escape("\x1B]0;\x07").0, empty_title);
+        assert_eq!(ansi_escaper::escape("\x1B]0\x07").0, empty_title);
+        assert_eq!("\x1B]0;\x07".to_ansi().parse_next(), empty_title);
+        assert_eq!("\x1B]0\x07".to_ansi().parse_next(), empty_title);
+    }
+
+    #[test]
+    fn osc_without_parameters() {
+        assert!(matches!(ansi_escaper::escape("\x1B]\x07").0, AnsiType::OSC { kind: OSCType::Unknown { .. } }));
+        assert!(matches!("\x1B]\x07".to_ansi().parse_next(), AnsiType::OSC { kind: OSCType::Unknown { .. } }));
+        assert!(matches!(OSCType::from("\x07", vec![]), OSCType::Unknown { .. }));
+    }
+
+    #[test]
+    fn sgr_reset_fast_path() {
+        let csi = AnsiType::CSI { kind: CSIType::Unknown { raw: String::new(), reason: String::new() } };
+        let slow_path = AnsiType::finish("m", csi.clone(), vec![String::from("0")]);
+        assert_eq!(ansi_escaper::escape("\x1B[0m"), (slow_path.clone(), 4));
+        assert_eq!(AnsiType::finish("m", csi, vec![String::new()]), slow_path);
+        assert_eq!(ansi_escaper::escape("\x1B[m"), (slow_path.clone(), 3));
+        assert_eq!(ansi_escaper::escape("\x1B[0mtext"), (slow_path, 4));
+    }
+
+    #[test]
+    fn sgr_colon_subparameters() {
+        let colon = ansi_escaper::escape("\x1B[38:2::255:0:0m").0;
+        let semicolon = ansi_escaper::escape("\x1B[38;2;255;0;0m").0;
+        assert_eq!(colon, semicolon);
+        assert_eq!("\x1B[38:2::255:0:0m".to_ansi().parse_next(), semicolon);
+        assert_eq!(ansi_escaper::escape("\x1B[48:2:255:0:0m").0, ansi_escaper::escape("\x1B[48;2;255;0;0m").0);
+        assert_eq!(ansi_escaper::escape("\x1B[38:5:9m").0, ansi_escaper::escape("\x1B[38;5;9m").0);
+
+        let AnsiType::CSI { kind: CSIType::SGR(params) } = colon else { panic!() };
+        assert_eq!(parse_sgr(params.as_slice()), vec![SgrParam::Foreground(Color::Rgb(255, 0, 0))]);
+
+        let AnsiType::CSI { kind: CSIType::SGR(params) } = ansi_escaper::escape("\x1B[1;4:3m").0 else { panic!() };
+        assert_eq!(params.parse(), vec![SgrParam::Bold, SgrParam::Underline(UnderlineStyle::Curly)]);
+        assert_eq!(format!("{:?}", params), "[1, 4:3]");
+        assert_eq!(AnsiType::CSI { kind: CSIType::SGR(params) }.encode(), "\x1B[1;4:3m");
+
+        // Plain codes never turn into underline styles, and back
+        for s in ["\x1B[4003m", "\x1B[4;3m", "\x1B[18446744073709551615m", "\x1B[4:0;4:5m"] {
+            let ansi = ansi_escaper::escape(s).0;
+            assert_eq!(ansi.encode(), s);
+            assert_eq!(ansi.byte_len(), s.len());
+        }
+        let AnsiType::CSI { kind: CSIType::SGR(params) } = ansi_escaper::escape("\x1B[4003m").0 else { panic!() };
+        assert_eq!(params.parse(), vec![SgrParam::Unknown(4003)]);
+        let AnsiType::CSI { kind: CSIType::SGR(params) } = ansi_escaper::escape("\x1B[4;3m").0 else { panic!() };
+        assert_eq!(params.parse(), vec![SgrParam::Underline(UnderlineStyle::Single), SgrParam::Italic]);
+        assert_eq!(styled_spans("\x1B[4:2mx")[0].0.underline, UnderlineStyle::Double);
+    }
+
+    /// Records every call as a string in its framebuffer.
+    #[cfg(feature = "term")]
+    #[derive(Clone)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    #[cfg(feature = "term")]
+    impl Recorder {
+        fn term() -> Term<Vec<String>> {
+            Term::new(Box::new(Recorder { events: vec![] }))
+        }
+    }
+
+    #[cfg(feature = "term")]
+    impl TermInterface<Vec<String>> for Recorder {
+        fn framebuffer(&self) -> &Vec<String> { &self.events }
+        fn framebuffer_mut(&mut self) -> &mut Vec<String> { &mut self.events }
+        fn completed_render(&mut self) {}
+        fn write(&mut self, s: String) { self.events.push(format!("write({})", s)) }
+        fn goto_x(&mut self, x: usize) { self.events.push(format!("goto_x({})", x)) }
+        fn goto_y(&mut self, y: usize) { self.events.push(format!("goto_y({})", y)) }
+        fn move_x(&mut self, x: isize) { self.events.push(format!("move_x({})", x)) }
+        fn move_y(&mut self, y: isize) { self.events.push(format!("move_y({})", y)) }
+        fn erase_in_display(&mut self, n: usize) { self.events.push(format!("erase_in_display({})", n)) }
+        fn erase_in_line(&mut self, n: usize) { self.events.push(format!("erase_in_line({})", n)) }
+        fn scroll_up(&mut self, n: usize) { self.events.push(format!("scroll_up({})", n)) }
+        fn scroll_down(&mut self, n: usize) { self.events.push(format!("scroll_down({})", n)) }
+        fn scroll_left(&mut self, n: usize) { self.events.push(format!("scroll_left({})", n)) }
+        fn scroll_right(&mut self, n: usize) { self.events.push(format!("scroll_right({})", n)) }
+        fn il(&mut self, n: usize) { self.events.push(format!("il({})", n)) }
+        fn delete_lines(&mut self, n: usize) { self.events.push(format!("delete_lines({})", n)) }
+        fn select_graphics_rendition(&mut self, n: SgrParams) { self.events.push(format!("sgr({:?})", n)) }
+        fn decstbm(&mut self, top: usize, bot: usize) { self.events.push(format!("decstbm({}, {})", top, bot)) }
+        fn resize(&mut self, rows: usize, cols: usize) { self.events.push(format!("resize({}, {})", rows, cols)) }
+        fn decslrm(&mut self, left: usize, right: usize) { self.events.push(format!("decslrm({}, {})", left, right)) }
+        fn dectcem(&mut self, show: bool) { self.events.push(format!("dectcem({})", show)) }
+        fn device_status_report(&mut self) -> (usize, usize) { (1, 1) }
+        fn unknown_csi(&mut self, s: String) { self.events.push(format!("unknown_csi({})", s)) }
+        fn set_title(&mut self, title: String) { self.events.push(format!("set_title({})", title)) }
+        fn unknown_osc(&mut self, s: String) { self.events.push(format!("unknown_osc({})", s)) }
+        fn unknown(&mut self, s: String) { self.events.push(format!("unknown({})", s)) }
+        fn bell(&mut self) { self.events.push(String::from("bell")) }
+        fn soft_reset(&mut self) { self.events.push(String::from("soft_reset")) }
+        fn notify(&mut self, title: Option<String>, body: String) { self.events.push(format!("notify({:?}, {})", title, body)); }
+        fn device_status(&mut self, request: DsrRequest) { self.events.push(format!("device_status({:?})", request)); }
+        fn set_char_protection(&mut self, protected: bool) { self.events.push(format!("set_char_protection({})", protected)) }
+        fn push_sgr(&mut self) { self.events.push(String::from("push_sgr")) }
+        fn pop_sgr(&mut self) { self.events.push(String::from("pop_sgr")) }
+        fn designate_charset(&mut self, slot: CharsetSlot, charset: Charset) {
+            self.events.push(format!("designate_charset({:?}, {:?})", slot, charset))
+        }
+        fn alignment_test(&mut self) { self.events.push(String::from("alignment_test")) }
+        fn line_attr(&mut self, attr: DecLineAttr) { self.events.push(format!("line_attr({:?})", attr)) }
+        fn clear_scrollback(&mut self) { self.events.push(String::from("clear_scrollback")) }
+        fn save_cursor(&mut self) { self.events.push(String::from("save_cursor")) }
+        fn restore_cursor(&mut self) { self.events.push(String::from("restore_cursor")) }
+        fn reset(&mut self) { self.events.push(String::from("reset")) }
+        fn set_cursor_style(&mut self, style: CursorStyle) { self.events.push(format!("set_cursor_style({:?})", style)) }
+        fn mouse_event(&mut self, button: usize, x: usize, y: usize, pressed: bool) {
+            self.events.push(format!("mouse_event({}, {}, {}, {})", button, x, y, pressed))
+        }
+        fn set_cursor_blink(&mut self, blink: bool) { self.events.push(format!("set_cursor_blink({})", blink)) }
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn write_bytes_split_codepoint() {
+        let mut term = Recorder::term();
+        let bytes = "€".as_bytes();
+        term.write_bytes(&bytes[..1]);
+        assert!(term.framebuffer().is_empty());
+        term.write_bytes(&bytes[1..]);
+        assert_eq!(term.framebuffer(), &vec![String::from("write(€)")]);
+
+        let mut term = Recorder::term();
+        term.write_bytes(b"a\xFFb");
+        assert_eq!(term.framebuffer(), &vec![String::from("write(a\u{FFFD}b)")]);
+    }
+
+    #[test]
+    fn builders_encode() {
+        assert_eq!(AnsiType::cursor_to(3, 5).encode(), "\x1B[3;5H");
+        assert_eq!(AnsiType::clear_screen().encode(), "\x1B[2J");
+        assert_eq!(AnsiType::sgr(&[1, 31]).encode(), "\x1B[1;31m");
+        assert_eq!(AnsiType::set_title("hi").encode(), "\x1B]0;hi\x07");
+
+        for ansi in [AnsiType::cursor_to(3, 5), AnsiType::clear_screen(), AnsiType::sgr(&[1, 31]), AnsiType::set_title("hi")] {
+            assert_eq!(ansi_escaper::escape(ansi.encode()).0, ansi);
+        }
+    }
+
+    #[test]
+    fn c1_introducers() {
+        let red = ansi_escaper::escape("\x1B[31m").0;
+        assert_eq!(ansi_escaper::escape("\u{9B}31m"), (red.clone(), 5));
+        assert_eq!(ansi_escaper::escape_bytes(b"\x9B31m"), (red.clone(), 4));
+        assert_eq!(ansi_escaper::escape_bytes(b"\x1B[31m"), (red.clone(), 5));
+        assert_eq!("\u{9B}31m".to_ansi().parse_next(), red);
+
+        let title = AnsiType::OSC { kind: OSCType::WindowTitle(String::from("hi")) };
+        assert_eq!(ansi_escaper::escape("\u{9D}0;hi\u{9C}"), (title.clone(), 8));
+        assert_eq!(ansi_escaper::escape_bytes(b"\x9D0;hi\x9C"), (title.clone(), 6));
+        assert_eq!("\u{9D}0;hi\u{9C}".to_ansi().parse_next(), title);
+
+        assert_eq!(ansi_escaper::escape("hi\u{9B}31m"), (AnsiType::Text(String::from("hi")), 2));
+        assert_eq!(ansi_escaper::escape_bytes(b"\xC3\xA9\x9B31m"), (AnsiType::Text(String::from("é")), 2));
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn control_chars() {
+        let mut escaper = "a\x07b".to_ansi();
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("a")));
+        assert_eq!(escaper.parse_next(), AnsiType::Control(ControlChar::Bell));
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("b")));
+        assert_eq!(ansi_escaper::escape("\x07b"), (AnsiType::Control(ControlChar::Bell), 1));
+        assert_eq!(ansi_escaper::escape("a\x08b"), (AnsiType::Text(String::from("a")), 1));
+
+        let mut term = Recorder::term();
+        term.write("a\x07\tb");
+        assert_eq!(term.framebuffer(), &vec![String::from("write(a)"), String::from("bell"), String::from("write(\t)"), String::from("write(b)")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn line_controls() {
+        let mut escaper = "ab\r\ncd".to_ansi();
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("ab")));
+        assert_eq!(escaper.parse_next(), AnsiType::Control(ControlChar::CarriageReturn));
+        assert_eq!(escaper.parse_next(), AnsiType::Control(ControlChar::LineFeed));
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("cd")));
+        assert_eq!(ansi_escaper::escape("\r\ncd"), (AnsiType::Control(ControlChar::CarriageReturn), 1));
+
+        let mut term = Recorder::term();
+        term.write("ab\r\ncd");
+        assert_eq!(term.framebuffer(), &vec![String::from("write(ab)"), String::from("goto_x(1)"), String::from("move_y(1)"), String::from("write(cd)")]);
+    }
+
+    #[test]
+    fn escaper_reset() {
+        let mut escaper = AnsiEscaper::new();
+        escaper.new_text("\x1B[3");
+        escaper.reset();
+        escaper.new_text("hello");
+        escaper.flush();
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("hello")));
+        assert_eq!(escaper.parse_next(), AnsiType::Incomplete);
+    }
+
+    #[test]
+    fn csi_default_params() {
+        assert_eq!(CSIType::default_params('J'), vec![0]);
+        assert_eq!(CSIType::default_params('A'), vec![1]);
+        assert_eq!(CSIType::default_params('H'), vec![1, 1]);
+        assert_eq!(ansi_escaper::escape("\x1B[J").0, AnsiType::CSI { kind: CSIType::ED(0) });
+        assert_eq!(ansi_escaper::escape("\x1B[K").0, AnsiType::CSI { kind: CSIType::EL(0) });
+        assert_eq!(ansi_escaper::escape("\x1B[A").0, AnsiType::CSI { kind: CSIType::CUU(1) });
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let sgr = AnsiType::sgr(&[1, 31]);
+        let json = serde_json::to_string(&sgr).unwrap();
+        assert_eq!(serde_json::from_str::<AnsiType>(&json).unwrap(), sgr);
+
+        let title = OSCType::WindowTitle(String::from("hi"));
+        let json = serde_json::to_string(&title).unwrap();
+        assert_eq!(serde_json::from_str::<OSCType>(&json).unwrap(), title);
+    }
+
+    #[cfg(feature = "unicode-width")]
+    #[test]
+    fn text_width() {
+        assert_eq!(ansi_escaper::text_width("a"), 1);
+        assert_eq!(ansi_escaper::text_width("世界"), 4);
+        assert_eq!(ansi_escaper::text_width("e\u{0301}"), 1);
+        assert_eq!(ansi_escaper::text_width("\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}"), 2);
+        assert_eq!(AnsiType::Text(String::from("世界")).display_width(), Some(4));
+        assert_eq!(AnsiType::SS2.display_width(), None);
+    }
+
+    #[test]
+    fn sgr_reset_detection() {
+        fn is_reset(s: &str) -> bool {
+            matches!(ansi_escaper::escape(s).0, AnsiType::CSI { kind } if kind.is_sgr_reset())
+        }
+        assert!(is_reset("\x1B[0m"));
+        assert!(is_reset("\x1B[m"));
+        assert!(!is_reset("\x1B[1m"));
+        assert!(!is_reset("\x1B[0;1m"));
+        assert!(CSIType::SGR(SgrParams::new()).is_sgr_reset());
+        assert!(!CSIType::ED(0).is_sgr_reset());
+    }
+
+    #[test]
+    fn window_ops() {
+        assert_eq!(ansi_escaper::escape("\x1B[18t").0, AnsiType::CSI { kind: CSIType::WindowOp(vec![18]) });
+        assert_eq!(ansi_escaper::escape("\x1B[22;0t").0, AnsiType::CSI { kind: CSIType::WindowOp(vec![22, 0]) });
+        assert_eq!("\x1B[22;0t".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::WindowOp(vec![22, 0]) });
+        assert_eq!(AnsiType::CSI { kind: CSIType::WindowOp(vec![22, 0]) }.encode(), "\x1B[22;0t");
+    }
+
+    #[test]
+    fn read_until_escape() {
+        assert_eq!(ansi_escaper::read_until_escape_char("hello\x1Bworld"), (String::from("hello"), 5));
+        assert_eq!(ansi_escaper::read_until_escape_char("héllo"), (String::from("héllo"), 6));
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn multiple_private_modes() {
+        let modes = vec![DecPrivateMode::CursorBlink, DecPrivateMode::CursorVisible];
+        assert_eq!(ansi_escaper::escape("\x1B[?12;25h").0, AnsiType::CSI { kind: CSIType::DECSET(modes.clone()) });
+        assert_eq!("\x1B[?12;25l".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::DECRST(modes) });
+        assert_eq!(ansi_escaper::escape("\x1B[?25l").0, AnsiType::CSI { kind: CSIType::DECTCEM(false) });
+
+        let mut term = Recorder::term();
+        term.write("\x1B[?12;25h");
+        assert_eq!(term.framebuffer(), &vec![String::from("set_cursor_blink(true)"), String::from("dectcem(true)")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn static_term() {
+        let mut term = TermStatic::new(Recorder { events: vec![] });
+        term.write("a\x1B[2A");
+        assert_eq!(term.framebuffer(), &vec![String::from("write(a)"), String::from("move_x(0)"), String::from("move_y(-2)")]);
+    }
+
+    #[test]
+    fn sgr_underline_color() {
+        let AnsiType::CSI { kind: CSIType::SGR(params) } = ansi_escaper::escape("\x1B[58;5;202m").0 else { panic!() };
+        assert_eq!(parse_sgr(params.as_slice()), vec![SgrParam::UnderlineColor(Color::Indexed(202))]);
+        let AnsiType::CSI { kind: CSIType::SGR(params) } = ansi_escaper::escape("\x1B[58;2;1;2;3m").0 else { panic!() };
+        assert_eq!(parse_sgr(params.as_slice()), vec![SgrParam::UnderlineColor(Color::Rgb(1, 2, 3))]);
+        let AnsiType::CSI { kind: CSIType::SGR(params) } = ansi_escaper::escape("\x1B[59m").0 else { panic!() };
+        assert_eq!(parse_sgr(params.as_slice()), vec![SgrParam::ResetUnderlineColor]);
+    }
+
+    #[test]
+    fn osc_non_ascii_title() {
+        let title = AnsiType::OSC { kind: OSCType::WindowTitle(String::from("café")) };
+        assert_eq!(ansi_escaper::escape("\x1B]0;café\x07"), (title.clone(), 10));
+        assert_eq!(ansi_escaper::escape("\x1B]0;café\x1B\\"), (title.clone(), 11));
+        assert_eq!("\x1B]0;café\x07".to_ansi().parse_next(), title);
+        assert_eq!(ansi_escaper::escape("\x1B]0;caf"), (AnsiType::Incomplete, 0));
+    }
+
+    #[test]
+    fn strip_escapes() {
+        assert_eq!(ansi_escaper::strip("\x1B[31mred\x1B[0m text"), "red text");
+        assert_eq!(ansi_escaper::strip("line\x1B]0;title\x07\n"), "line\n");
+        assert_eq!(ansi_escaper::strip("text\x1B["), "text");
+    }
+
+    #[test]
+    fn visible_length() {
+        assert_eq!(ansi_escaper::visible_length("\x1B[1mhi\x1B[0m"), 2);
+        assert_eq!(ansi_escaper::visible_length("a\tb\n"), 2);
+
+        #[cfg(feature = "unicode-width")]
+        assert_eq!(ansi_escaper::visible_length("\x1B[31m日本\x1B[0m"), 4);
+        #[cfg(not(feature = "unicode-width"))]
+        assert_eq!(ansi_escaper::visible_length("\x1B[31m日本\x1B[0m"), 2);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn soft_reset() {
+        assert_eq!(ansi_escaper::escape("\x1B[!p"), (AnsiType::CSI { kind: CSIType::DECSTR }, 4));
+        assert_eq!("\x1B[!p".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::DECSTR });
+        assert_eq!(AnsiType::CSI { kind: CSIType::DECSTR }.encode(), "\x1B[!p");
+
+        let mut term = Recorder::term();
+        term.write("\x1B[!p");
+        assert_eq!(term.framebuffer(), &vec![String::from("soft_reset")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn intermediate_bytes() {
+        assert_eq!(ansi_escaper::escape("\x1B[2 q"), (AnsiType::CSI { kind: CSIType::DECSCUSR(2) }, 5));
+        assert_eq!(ansi_escaper::escape("\x1B[ q").0, AnsiType::CSI { kind: CSIType::DECSCUSR(0) });
+        assert_eq!("\x1B[5 q".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::DECSCUSR(5) });
+        assert_eq!(AnsiType::CSI { kind: CSIType::DECSCUSR(2) }.encode(), "\x1B[2 q");
+
+        assert!(!matches!(ansi_escaper::escape("\x1B[2q").0, AnsiType::CSI { kind: CSIType::DECSCUSR(_) }));
+        assert!(matches!(ansi_escaper::escape("\x1B[ 2q"), (AnsiType::Unknown { .. }, 5)));
+
+        let mut term = Recorder::term();
+        term.write("\x1B[6 q");
+        assert_eq!(term.framebuffer(), &vec![String::from("set_cursor_style(SteadyBar)")]);
+    }
+
+    #[test]
+    fn screen_state() {
+        let mut screen = ScreenState::new(24, 80);
+        for ansi in "\x1B[5;10Hab\x1B[2A\x1B[3D\x1B[31m\r\n\x1B[1m".to_ansi() {
+            if ansi == AnsiType::Incomplete {
+                break;
+            }
+            screen.apply(&ansi);
+        }
+        assert_eq!(screen.position(), (4, 1));
+        assert_eq!((screen.style.fg, screen.style.bold), (Color::Red, true));
+
+        screen.apply(&AnsiType::cursor_to(100, 200));
+        assert_eq!(screen.position(), (24, 80));
+        screen.apply(&AnsiType::CSI { kind: CSIType::CUB(500) });
+        screen.apply(&AnsiType::sgr(&[0]));
+        assert_eq!((screen.position(), screen.style), ((24, 1), SgrStyle::default()));
+
+        // Only the resulting attributes are kept, not every sequence that led to them
+        for _ in 0..1000 {
+            screen.apply(&AnsiType::sgr(&[1, 38, 5, 202]));
+        }
+        screen.apply(&AnsiType::sgr(&[22]));
+        assert_eq!((screen.style.fg, screen.style.bold), (Color::Indexed(202), false));
+        screen.apply(&"\x1B[!p".to_ansi().parse_next());
+        assert_eq!(screen.style, SgrStyle::default());
+    }
+
+    #[test]
+    fn screen_state_wrap() {
+        let mut screen = ScreenState::new(24, 10);
+        screen.apply(&AnsiType::Text(String::from("0123456789")));
+        // Deferred wrap, the cursor waits on the last column
+        assert_eq!(screen.position(), (1, 10));
+        screen.apply(&AnsiType::sgr(&[0]));
+        screen.apply(&AnsiType::Text(String::from("ab")));
+        assert_eq!(screen.position(), (2, 3));
+
+        screen.apply(&"\x1B[?7l".to_ansi().parse_next());
+        assert!(!screen.wrap);
+        screen.apply(&AnsiType::Text(String::from("0123456789abc")));
+        assert_eq!(screen.position(), (2, 10));
+
+        screen.apply(&"\x1B[?7h".to_ansi().parse_next());
+        screen.apply(&AnsiType::cursor_to(24, 10));
+        screen.apply(&AnsiType::Text(String::from("xy")));
+        assert_eq!(screen.position(), (24, 2));
+
+        // Sequences that don't move the cursor keep the pending wrap
+        for seq in ["\x1B(0", "\x1B]0;title\x07", "\x1B[?25l", "\x1B[5y"] {
+            let mut screen = ScreenState::new(24, 10);
+            screen.apply(&AnsiType::Text(String::from("0123456789")));
+            screen.apply(&seq.to_ansi().parse_next());
+            screen.apply(&AnsiType::Text(String::from("a")));
+            assert_eq!(screen.position(), (2, 2), "{:?}", seq);
+        }
+        for seq in ["\r", "\x1B[D", "\x1B[10G", "\x1BM", "\x1B[1;10H"] {
+            let mut screen = ScreenState::new(24, 10);
+            screen.apply(&AnsiType::Text(String::from("0123456789")));
+            screen.apply(&seq.to_ansi().parse_next());
+            screen.apply(&AnsiType::Text(String::from("a")));
+            assert_eq!(screen.position().0, 1, "{:?}", seq);
+        }
+    }
+
+    #[test]
+    fn unknown_raw() {
+        let input = "\x1B[ 2q";
+        let (ansi, len) = ansi_escaper::escape(input);
+        assert!(matches!(&ansi, AnsiType::Unknown { raw, .. } if raw == input));
+        assert_eq!(ansi.encode(), &input[..len]);
+
+        let (ansi, len) = ansi_escaper::escape("\x1Bzrest");
+        assert_eq!(len, 2);
+        assert!(matches!(ansi, AnsiType::Unknown { raw, reason } if raw == "\x1Bz" && !reason.is_empty()));
+        assert_eq!("\x1Bzrest".to_ansi().parse_next().encode(), "\x1Bz");
+
+        // Unsupported CSI and OSC commands keep their input as well
+        for input in ["\x1B[5y", "\u{9B}5y", "\x1B]99;x\x07", "\x1B]99;x\x1B\\"] {
+            let (ansi, len) = ansi_escaper::escape(input);
+            assert_eq!((ansi.encode(), len), (String::from(input), input.len()));
+            assert_eq!(ansi.byte_len(), len);
+            assert_eq!(input.to_ansi().parse_next().encode(), input);
+        }
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn term_unknown_pass_through() {
+        let forwarded = Rc::new(RefCell::new(String::new()));
+        let sink = forwarded.clone();
+        let mut term = Recorder::term();
+        term.set_event_filter(move |ansi| {
+            sink.borrow_mut().push_str(&ansi.encode());
+            true
+        });
+        let input = "a\x1Bzq\x1B[5y\x1B[3\x1B[1mb\x1B]99;x\x07\x1B[3;\x01m";
+        term.write(&input[..9]);
+        term.write(&input[9..]);
+        assert_eq!(*forwarded.borrow(), input);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn mouse_sgr() {
+        let press = CSIType::MouseSgr { button: 0, x: 12, y: 34, pressed: true };
+        let release = CSIType::MouseSgr { button: 0, x: 12, y: 34, pressed: false };
+        assert_eq!(ansi_escaper::escape("\x1B[<0;12;34M"), (AnsiType::CSI { kind: press.clone() }, 11));
+        assert_eq!(ansi_escaper::escape("\x1B[<0;12;34m").0, AnsiType::CSI { kind: release.clone() });
+        assert_eq!("\x1B[<0;12;34m".to_ansi().parse_next(), AnsiType::CSI { kind: release });
+        assert_eq!(AnsiType::CSI { kind: press }.encode(), "\x1B[<0;12;34M");
+
+        let mut term = Recorder::term();
+        term.write("\x1B[<2;5;6M");
+        assert_eq!(term.framebuffer(), &vec![String::from("mouse_event(2, 5, 6, true)")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn mouse_x10() {
+        // Left button press at column 10, row 5
+        let click = CSIType::MouseX10 { button: 0, x: 10, y: 5 };
+        assert_eq!(ansi_escaper::escape("\x1B[M *%rest"), (AnsiType::CSI { kind: click.clone() }, 6));
+        assert_eq!(ansi_escaper::escape("\x1B[M *"), (AnsiType::Incomplete, 0));
+        assert_eq!(AnsiType::CSI { kind: click.clone() }.encode(), "\x1B[M *%");
+
+        let mut escaper = "\x1B[M *".to_ansi();
+        assert_eq!(escaper.parse_next(), AnsiType::Incomplete);
+        escaper.new_text("%");
+        assert_eq!(escaper.parse_next(), AnsiType::CSI { kind: click });
+
+        let mut term = Recorder::term();
+        term.write("\x1B[M#*%");
+        assert_eq!(term.framebuffer(), &vec![String::from("mouse_event(3, 10, 5, false)")]);
+    }
+
+    #[test]
+    fn default_impls() {
+        let mut escaper = AnsiEscaper::default();
+        escaper.new_text("hi\x1B[1m");
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("hi")));
+        assert_eq!(escaper.parse_next(), AnsiType::sgr(&[1]));
+
+        let screen = ScreenState::default();
+        assert_eq!((screen.rows, screen.cols, screen.position()), (24, 80, (1, 1)));
+    }
+
+    #[test]
+    fn escaper_iterator_ends() {
+        let events = "\x1B[31mhi".to_ansi().collect::<Vec<AnsiType>>();
+        assert_eq!(events, vec![AnsiType::sgr(&[31]), AnsiType::Text(String::from("hi"))]);
+
+        let mut escaper = "\x1B[M ".to_ansi();
+        assert_eq!(escaper.next(), None);
+        escaper.new_text("!!");
+        assert_eq!(escaper.next(), Some(AnsiType::CSI { kind: CSIType::MouseX10 { button: 0, x: 1, y: 1 } }));
+        assert_eq!(escaper.next(), None);
+    }
+
+    #[test]
+    fn to_ansi_string() {
+        let owned = String::from("\x1B[0m");
+        assert_eq!(owned.to_ansi().parse_next(), AnsiType::sgr(&[0]));
+        let borrowed: &String = &owned;
+        assert_eq!(borrowed.to_ansi().parse_next(), AnsiType::sgr(&[0]));
+        assert_eq!(String::from("hi").to_ansi().parse_next(), AnsiType::Text(String::from("hi")));
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn full_reset() {
+        assert_eq!("\x1Bc".to_ansi().parse_next(), AnsiType::RIS);
+
+        let mut term = Recorder::term();
+        term.write("\x1Bc\x1B[!p");
+        assert_eq!(term.framebuffer(), &vec![String::from("reset"), String::from("soft_reset")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn save_cursor_or_margins() {
+        assert_eq!(ansi_escaper::escape("\x1B[s"), (AnsiType::CSI { kind: CSIType::SCOSC }, 3));
+        assert_eq!(ansi_escaper::escape("\x1B[u").0, AnsiType::CSI { kind: CSIType::SCORC });
+        assert_eq!(ansi_escaper::escape("\x1B[1;80s").0, AnsiType::CSI { kind: CSIType::DECSLRM(1, 80) });
+        assert_eq!("\x1B[s".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::SCOSC });
+        assert_eq!("\x1B[1;80s".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::DECSLRM(1, 80) });
+
+        let mut term = Recorder::term();
+        term.write("\x1B[s\x1B[u");
+        assert_eq!(term.framebuffer(), &vec![String::from("save_cursor"), String::from("restore_cursor")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn coordinate_base() {
+        let mut term = Recorder::term();
+        term.write("\x1B[5;10H");
+        let one_based = term.framebuffer().clone();
+
+        let mut term = Recorder::term();
+        term.set_coordinate_base(0);
+        term.write("\x1B[5;10H\x1B[3G");
+        assert_eq!(one_based, vec![String::from("goto_x(10)"), String::from("goto_y(5)")]);
+        assert_eq!(term.framebuffer(), &vec![String::from("goto_x(9)"), String::from("goto_y(4)"), String::from("goto_x(2)")]);
+    }
+
+    #[test]
+    fn try_escape_errors() {
+        assert_eq!(ansi_escaper::try_escape("\x1B[3;\x01m"), Err(AnsiError::IllegalParameterByte { byte: 0x01, offset: 4 }));
+        assert_eq!(ansi_escaper::try_escape("\u{9B}3;\x01m"), Err(AnsiError::IllegalParameterByte { byte: 0x01, offset: 4 }));
+        assert_eq!(ansi_escaper::try_escape("\x1B[ 2q"), Err(AnsiError::IllegalParameterByte { byte: b'2', offset: 3 }));
+        assert_eq!(ansi_escaper::try_escape("\x1B[3"), Err(AnsiError::Incomplete));
+        assert_eq!(ansi_escaper::try_escape("\x1B[3m"), Ok((AnsiType::sgr(&[3]), 4)));
+
+        // `escape` reports the same error as `Unknown`
+        let (ansi, len) = ansi_escaper::escape("\x1B[3;\x01mtext");
+        assert_eq!(len, 6);
+        assert!(matches!(ansi, AnsiType::Unknown { raw, .. } if raw == "\x1B[3;\x01m"));
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn erase_regions() {
+        let regions = [EraseRegion::ToEnd, EraseRegion::ToStart, EraseRegion::All, EraseRegion::AllWithScrollback];
+        for (n, region) in regions.iter().enumerate() {
+            assert_eq!(EraseRegion::from(n), *region);
+            assert_eq!(region.value(), n);
+        }
+        assert_eq!(EraseRegion::from(7), EraseRegion::ToEnd);
+
+        let mut term = Recorder::term();
+        term.write("\x1B[2J\x1B[9J\x1B[1K\x1B[3K");
+        assert_eq!(term.framebuffer(), &vec![
+            String::from("erase_in_display(2)"),
+            String::from("erase_in_display(0)"),
+            String::from("erase_in_line(1)"),
+            String::from("erase_in_line(0)"),
+        ]);
+    }
+
+    #[test]
+    fn nested_escapes() {
+        let title = AnsiType::OSC { kind: OSCType::WindowTitle(String::from("title")) };
+        assert_eq!(ansi_escaper::escape("\x1B]0;title\x1B\\rest"), (title, 11));
+
+        // A truncated CSI is cut off where the next one starts
+        let input = "\x1B[3\x1B[31m";
+        let (ansi, len) = ansi_escaper::escape(input);
+        assert_eq!(len, 3);
+        assert!(matches!(ansi, AnsiType::Unknown { raw, .. } if raw == "\x1B[3"));
+        assert_eq!(ansi_escaper::try_escape(input), Err(AnsiError::Interrupted));
+        assert_eq!(ansi_escaper::escape(&input[len..]), (AnsiType::sgr(&[31]), 5));
+        let parsed = format!("{}hi", input).to_ansi().parse_all();
+        assert!(matches!(&parsed[0], AnsiType::Unknown { reason, .. } if *reason == format!("{}", AnsiError::Interrupted)));
+        assert_eq!(parsed[1..], [AnsiType::sgr(&[31]), AnsiType::Text(String::from("hi"))]);
+        // C0 controls don't end it, but make it invalid
+        let parsed = "\x1B[3;\x01mhi".to_ansi().parse_all();
+        assert!(matches!(&parsed[0], AnsiType::Unknown { reason, .. } if reason.contains("0x01")));
+        assert_eq!(parsed[1..], [AnsiType::Text(String::from("hi"))]);
+
+        // Strings only end at ST
+        assert_eq!(ansi_escaper::escape("\x1BPq#0;2\x1B\\rest"), (AnsiType::DCS, 9));
+        assert_eq!(ansi_escaper::escape("\x1B_data\u{9C}"), (AnsiType::APC, 8));
+        assert_eq!(ansi_escaper::escape("\x1BPq#0"), (AnsiType::Incomplete, 0));
+        assert_eq!(ansi_escaper::escape("\x1Bc"), (AnsiType::RIS, 2));
+        assert_eq!(ansi_escaper::escape("\x1B\\"), (AnsiType::ST, 2));
+    }
+
+    #[test]
+    fn sgr_style() {
+        let mut style = SgrStyle::default();
+        style.apply_sgr(&[1, 31]);
+        assert!(style.bold);
+        assert_eq!(style.fg, Color::Red);
+
+        style.apply_sgr(&[4, 48, 5, 200]);
+        assert_eq!((style.underline, style.bg), (UnderlineStyle::Single, Color::Indexed(200)));
+
+        style.apply_sgr(&[0]);
+        assert_eq!(style, SgrStyle::default());
+    }
+
+    #[test]
+    fn display_format() {
+        let cases = [
+            (AnsiType::Text(String::from("hi")), "Text(\"hi\")"),
+            (AnsiType::Control(ControlChar::Tab), "Control(Tab)"),
+            (AnsiType::CSI { kind: CSIType::CUU(3) }, "CUU { n: 3 }"),
+            (AnsiType::cursor_to(2, 5), "CUP { n: 2, m: 5 }"),
+            (AnsiType::sgr(&[1, 31]), "SGR { n: [1, 31] }"),
+            (AnsiType::CSI { kind: CSIType::DECSTR }, "DECSTR { }"),
+            (AnsiType::set_title("t"), "OSC { WindowTitle: \"t\" }"),
+            (AnsiType::Unknown { raw: String::from("\x1Bz"), reason: String::from("bad") }, "Unknown: \"bad\""),
+            (AnsiType::RIS, "RIS"),
+        ];
+        for (ansi, expected) in cases {
+            assert_eq!(format!("{}", ansi), expected);
+        }
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn cursor_styles() {
+        let styles = [
+            CursorStyle::Default,
+            CursorStyle::BlinkingBlock,
+            CursorStyle::SteadyBlock,
+            CursorStyle::BlinkingUnderline,
+            CursorStyle::SteadyUnderline,
+            CursorStyle::BlinkingBar,
+            CursorStyle::SteadyBar,
+        ];
+        for (n, style) in styles.iter().enumerate() {
+            let mut term = Recorder::term();
+            term.write(format!("\x1B[{} q", n));
+            assert_eq!(term.framebuffer(), &vec![format!("set_cursor_style({:?})", style)]);
+        }
+        let mut term = Recorder::term();
+        term.write("\x1B[ q");
+        assert_eq!(term.framebuffer(), &vec![String::from("set_cursor_style(Default)")]);
+    }
+
+    #[test]
+    fn request_mode() {
+        let query = AnsiType::CSI { kind: CSIType::DecRequestMode(2004) };
+        assert_eq!(ansi_escaper::escape("\x1B[?2004$p"), (query.clone(), 9));
+        assert_eq!("\x1B[?2004$p".to_ansi().parse_next(), query);
+        assert_eq!(query.encode(), "\x1B[?2004$p");
+        assert_eq!(ansi_escaper::decrpm(2004, 1), "\x1B[?2004;1$y");
+    }
+
+    #[test]
+    fn from_byte() {
+        assert_eq!(AnsiType::from_byte(b'['), AnsiType::from("["));
+        assert_eq!(AnsiType::from_byte(0x9B), AnsiType::from("["));
+        assert_eq!(AnsiType::from_byte(b']'), AnsiType::from("]"));
+        assert_eq!(AnsiType::from_byte(0x9D), AnsiType::from("]"));
+        assert_eq!(AnsiType::from_byte(b'P'), AnsiType::DCS);
+        assert_eq!(AnsiType::from_byte(0x9C), AnsiType::ST);
+        assert_eq!(AnsiType::from_byte(0x9E), AnsiType::PM);
+        assert_eq!(AnsiType::from_byte(b'c'), AnsiType::RIS);
+        assert_eq!(AnsiType::from_byte(b'('), AnsiType::SETCHARSET);
+        assert!(matches!(AnsiType::from_byte(0xFF), AnsiType::Unknown { reason, .. } if reason.contains("0xff")));
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn event_filter() {
+        let mut term = Recorder::term();
+        term.set_event_filter(|ansi| !matches!(ansi, AnsiType::OSC { .. }));
+        term.write("a\x1B]0;title\x07b");
+        assert_eq!(term.framebuffer(), &vec![String::from("write(a)"), String::from("write(b)")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn clear_scrollback() {
+        let mut term = Recorder::term();
+        term.write("\x1B[3J\x1B[2J");
+        assert_eq!(term.framebuffer(), &vec![String::from("clear_scrollback"), String::from("erase_in_display(2)")]);
+    }
+
+    #[test]
+    fn osc_config() {
+        let clipboard = AnsiType::OSC { kind: OSCType::Clipboard { selection: String::from("c"), data: String::from("eA==") } };
+        let link = AnsiType::OSC { kind: OSCType::Hyperlink { params: String::new(), uri: String::from("https://a.b/c;d") } };
+        assert_eq!(ansi_escaper::escape("\x1B]52;c;eA==\x07"), (clipboard.clone(), 12));
+        assert_eq!(ansi_escaper::escape("\x1B]8;;https://a.b/c;d\x1B\\").0, link);
+        assert_eq!("\x1B]8;;https://a.b/c;d\x07".to_ansi().parse_next(), link);
+        assert_eq!(clipboard.encode(), "\x1B]52;c;eA==\x07");
+
+        let config = OscConfig { allow_clipboard: false, ..OscConfig::default() };
+        let (ansi, len) = ansi_escaper::escape_with_config("\x1B]52;c;x\x07", &config);
+        assert!(matches!(ansi, AnsiType::OSC { kind: OSCType::Unknown { .. } }));
+        assert_eq!(len, 9);
+        assert_eq!(ansi_escaper::escape_with_config("\x1B]0;t\x07", &config).0, AnsiType::set_title("t"));
+
+        let mut escaper = AnsiEscaper::new();
+        escaper.set_osc_config(config);
+        escaper.new_text("\x1B]52;c;x\x07");
+        assert!(matches!(escaper.parse_next(), AnsiType::OSC { kind: OSCType::Unknown { .. } }));
+    }
+
+    #[test]
+    fn display_csi_osc() {
+        assert_eq!(format!("{}", CSIType::CUU(3)), "CUU { n: 3 }");
+        assert_eq!(format!("{}", CSIType::SGR(SgrParams::from_iter([0]))), "SGR { n: [0] }");
+        assert_eq!(format!("{}", OSCType::WindowTitle(String::from("t"))), "OSC { WindowTitle: \"t\" }");
+        assert_eq!(format!("{}", AnsiType::CSI { kind: CSIType::CUU(3) }), format!("{}", CSIType::CUU(3)));
+    }
+
+    #[test]
+    fn screen_state_clamp() {
+        let mut screen = ScreenState::new(24, 80);
+        let mut apply = |kind: CSIType| {
+            screen.apply(&AnsiType::CSI { kind });
+            screen.position()
+        };
+        assert_eq!(apply(CSIType::CUP(30, 90)), (24, 80));
+        assert_eq!(apply(CSIType::CUP(0, 0)), (1, 1));
+        assert_eq!(apply(CSIType::HVP(25, 81)), (24, 80));
+        assert_eq!(apply(CSIType::CHA(100)), (24, 80));
+        assert_eq!(apply(CSIType::CVA(0)), (1, 80));
+        assert_eq!(apply(CSIType::CUU(5)), (1, 80));
+        assert_eq!(apply(CSIType::CUB(usize::MAX)), (1, 1));
+        assert_eq!(apply(CSIType::CUD(usize::MAX)), (24, 1));
+        assert_eq!(apply(CSIType::CUF(usize::MAX)), (24, 80));
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn scroll_left_right() {
+        assert_eq!(ansi_escaper::escape("\x1B[2 @"), (AnsiType::CSI { kind: CSIType::SL(2) }, 5));
+        assert_eq!(ansi_escaper::escape("\x1B[3 A"), (AnsiType::CSI { kind: CSIType::SR(3) }, 5));
+        assert_eq!(ansi_escaper::escape("\x1B[ @").0, AnsiType::CSI { kind: CSIType::SL(1) });
+        assert_eq!(AnsiType::CSI { kind: CSIType::SR(3) }.encode(), "\x1B[3 A");
+        // Without the space `A` is still CUU
+        assert_eq!(ansi_escaper::escape("\x1B[3A").0, AnsiType::CSI { kind: CSIType::CUU(3) });
+
+        let mut term = Recorder::term();
+        term.write("\x1B[2 @\x1B[3 A");
+        assert_eq!(term.framebuffer(), &vec![String::from("scroll_left(2)"), String::from("scroll_right(3)")]);
+    }
+
+    #[test]
+    fn escaper_peek() {
+        let mut escaper = "\x1B[31mhi\x1B[0m".to_ansi();
+        let peeked = escaper.peek();
+        assert_eq!(peeked, AnsiType::sgr(&[31]));
+        assert_eq!(escaper.parse_next(), peeked);
+        assert_eq!(escaper.peek(), AnsiType::Text(String::from("hi")));
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("hi")));
+        assert_eq!(escaper.parse_next(), AnsiType::sgr(&[0]));
+        assert_eq!(escaper.peek(), AnsiType::Incomplete);
+
+        // A cut off sequence stays buffered instead of being dropped
+        let mut escaper = "\x1B[3".to_ansi();
+        assert_eq!(escaper.peek(), AnsiType::Incomplete);
+        assert_eq!(escaper.parse_next(), AnsiType::Incomplete);
+        escaper.new_text("1m");
+        assert_eq!(escaper.parse_next(), AnsiType::sgr(&[31]));
+
+        let mut escaper = "\r\nx".to_ansi();
+        assert_eq!(escaper.peek(), AnsiType::Control(ControlChar::CarriageReturn));
+        assert_eq!(escaper.parse_all(), vec![
+            AnsiType::Control(ControlChar::CarriageReturn),
+            AnsiType::Control(ControlChar::LineFeed),
+            AnsiType::Text(String::from("x")),
+        ]);
+    }
+
+    #[test]
+    fn combining_across_feeds() {
+        let mut escaper = AnsiEscaper::new();
+        escaper.new_text("e");
+        escaper.new_text("\u{0301}");
+        escaper.flush();
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("e\u{0301}")));
+        assert_eq!(escaper.parse_next(), AnsiType::Incomplete);
+
+        // Parsing in between doesn't let the base character out before its accent
+        let mut escaper = AnsiEscaper::new();
+        escaper.new_text("e");
+        assert_eq!(escaper.parse_next(), AnsiType::Incomplete);
+        escaper.new_text("\u{0301}");
+        assert_eq!(escaper.parse_next(), AnsiType::Incomplete);
+        escaper.flush();
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("e\u{0301}")));
+
+        escaper.new_text("ab");
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("a")));
+        escaper.new_text("\u{0301}\x1B[1m");
+        assert_eq!(escaper.parse_all(), vec![AnsiType::Text(String::from("b\u{0301}")), AnsiType::sgr(&[1])]);
+
+        // After a flush the next text starts a new grapheme
+        escaper.new_text("x");
+        escaper.flush();
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("x")));
+        escaper.new_text("\u{0301}");
+        escaper.flush();
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("\u{0301}")));
+
+        let mut escaper = "a\x1B[".to_ansi();
+        escaper.new_text("1mb");
+        escaper.flush();
+        assert_eq!(escaper.collect::<Vec<AnsiType>>(), vec![
+            AnsiType::Text(String::from("a")),
+            AnsiType::sgr(&[1]),
+            AnsiType::Text(String::from("b")),
+        ]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn absolute_axis() {
+        assert_eq!(ansi_escaper::escape("\x1B[10G").0, AnsiType::CSI { kind: CSIType::CHA(10) });
+        assert_eq!(ansi_escaper::escape("\x1B[5d").0, AnsiType::CSI { kind: CSIType::CVA(5) });
+
+        let mut term = Recorder::term();
+        term.write("\x1B[10G");
+        assert_eq!(term.framebuffer(), &vec![String::from("goto_x(10)")]);
+        let mut term = Recorder::term();
+        term.write("\x1B[5d");
+        assert_eq!(term.framebuffer(), &vec![String::from("goto_y(5)")]);
+
+        let mut screen = ScreenState::new(24, 80);
+        screen.apply(&ansi_escaper::escape("\x1B[10G").0);
+        screen.apply(&ansi_escaper::escape("\x1B[5d").0);
+        assert_eq!(screen.position(), (5, 10));
+    }
+
+    #[test]
+    fn hpa() {
+        assert_eq!(ansi_escaper::escape("\x1B[15`"), (AnsiType::CSI { kind: CSIType::CHA(15) }, 5));
+        assert_eq!(ansi_escaper::escape("\x1B[`").0, AnsiType::CSI { kind: CSIType::CHA(1) });
+        assert_eq!("\x1B[15`".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::CHA(15) });
+    }
+
+    #[cfg(feature = "term")]
+    #[cfg(feature = "log")]
+    #[test]
+    fn log_events() {
+        use std::sync::Mutex;
+
+        static RECORDS: Mutex<Vec<(log::Level, String)>> = Mutex::new(Vec::new());
+        struct Capture;
+        impl log::Log for Capture {
+            fn enabled(&self, _metadata: &log::Metadata) -> bool { true }
+            fn log(&self, record: &log::Record) {
+                RECORDS.lock().unwrap().push((record.level(), format!("{}", record.args())));
+            }
+            fn flush(&self) {}
+        }
+        static CAPTURE: Capture = Capture;
+        log::set_logger(&CAPTURE).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut term = Recorder::term();
+        term.write("\x1B[1m");
+        let sgr = format!("{}", AnsiType::sgr(&[1]));
+        let records = RECORDS.lock().unwrap();
+        assert!(records.contains(&(log::Level::Trace, sgr)));
+        assert!(records.iter().any(|(level, _)| *level == log::Level::Debug));
+    }
+
+    #[test]
+    fn byte_len_matches_encode() {
+        // xorshift, so failures are reproducible without pulling in a property testing crate
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            // Mix small and huge values so every digit count shows up
+            (seed >> (seed % 64)) as usize
+        };
+        for _ in 0..500 {
+            let (n, m) = (rand(), rand());
+            let text = "é€x".repeat(n % 4);
+            let params = (0..n % 5).map(|_| rand()).collect::<Vec<usize>>();
+            let mut sgr = SgrParams::from(params.as_slice());
+            sgr.push(4);
+            sgr.push_sub(m);
+            let modes = params.iter().map(|p| DecPrivateMode::from(*p)).collect::<Vec<DecPrivateMode>>();
+            let kinds = vec![
+                CSIType::CUU(n), CSIType::CUD(n), CSIType::CUF(n), CSIType::CUB(n), CSIType::CNL(n),
+                CSIType::CPL(n), CSIType::CHA(n), CSIType::CVA(n), CSIType::CUP(n, m), CSIType::ED(n),
+                CSIType::EL(n), CSIType::SU(n), CSIType::SD(n), CSIType::SL(n), CSIType::SR(n),
+                CSIType::IL(n), CSIType::DL(n), CSIType::HVP(n, m), CSIType::SGR(sgr), CSIType::DECTCEM(n % 2 == 0),
+                CSIType::DECSET(modes.clone()), CSIType::DECRST(modes), CSIType::DECSTBM(n, m),
+                CSIType::DECSLRM(n, m), CSIType::SCOSC, CSIType::SCORC, CSIType::WindowOp(params),
+                CSIType::DECSTR, CSIType::PushSgr, CSIType::PopSgr, CSIType::DECSCUSR(n), CSIType::DECSCA(m),
+                CSIType::MouseSgr { button: n, x: m, y: n, pressed: m % 2 == 0 },
+                CSIType::DecRequestMode(m), CSIType::DSR(DsrRequest::Status), CSIType::DSR(DsrRequest::ExtendedCursorPosition),
+                CSIType::MouseX10 { button: n as u8, x: m % 300, y: n % 300 },
+                CSIType::Unknown { raw: text.clone(), reason: String::new() },
+            ];
+            let mut events = kinds.into_iter().map(|kind| AnsiType::CSI { kind }).collect::<Vec<AnsiType>>();
+            events.extend([
+                AnsiType::Text(text.clone()),
+                AnsiType::Control(ControlChar::Tab),
+                AnsiType::OSC { kind: OSCType::WindowTitle(text.clone()) },
+                AnsiType::OSC { kind: OSCType::Clipboard { selection: String::from("c"), data: text.clone() } },
+                AnsiType::OSC { kind: OSCType::Hyperlink { params: text.clone(), uri: String::from("a") } },
+                AnsiType::OSC { kind: OSCType::WorkingDirectory { uri: text.clone() } },
+                AnsiType::OSC { kind: OSCType::Notification { title: None, body: text.clone() } },
+                AnsiType::OSC { kind: OSCType::Notification { title: Some(text.clone()), body: String::from("b") } },
+                AnsiType::OSC { kind: OSCType::Unknown { raw: text.clone(), reason: String::new() } },
+                AnsiType::Unknown { raw: text, reason: String::new() },
+                AnsiType::SS2, AnsiType::SS3, AnsiType::DCS, AnsiType::ST, AnsiType::RIS, AnsiType::Index,
+                AnsiType::ReverseIndex, AnsiType::NextLine,
+                AnsiType::KeypadApplication, AnsiType::KeypadNumeric, AnsiType::SOS, AnsiType::PM,
+                AnsiType::APC, AnsiType::SETCHARSET, AnsiType::Incomplete, AnsiType::DecAlignmentTest,
+                AnsiType::DecLineAttr(DecLineAttr::DoubleHeightBottom),
+                AnsiType::DesignateCharset { slot: CharsetSlot::G3, charset: Charset::Unknown(char::from_u32(m as u32 % 0x800).unwrap_or_default()) },
+            ]);
+            for ansi in events {
+                assert_eq!(ansi.byte_len(), ansi.encode().len(), "{:?}", ansi);
+            }
+        }
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn dec_line_sequences() {
+        assert_eq!(ansi_escaper::escape("\x1B#8"), (AnsiType::DecAlignmentTest, 3));
+        assert_eq!(ansi_escaper::escape("\x1B#6"), (AnsiType::DecLineAttr(DecLineAttr::DoubleWidth), 3));
+        assert_eq!(ansi_escaper::escape("\x1B#3").0, AnsiType::DecLineAttr(DecLineAttr::DoubleHeightTop));
+        assert_eq!(ansi_escaper::escape("\x1B#").0, AnsiType::Incomplete);
+        assert!(matches!(ansi_escaper::escape("\x1B#9").0, AnsiType::Unknown { .. }));
+        assert_eq!(AnsiType::DecLineAttr(DecLineAttr::DoubleWidth).encode(), "\x1B#6");
+        assert_eq!(AnsiType::DecAlignmentTest.encode(), "\x1B#8");
+
+        let mut escaper = "\x1B#".to_ansi();
+        assert_eq!(escaper.parse_next(), AnsiType::Incomplete);
+        escaper.new_text("8x");
+        assert_eq!(escaper.parse_next(), AnsiType::DecAlignmentTest);
+
+        let mut term = Recorder::term();
+        term.write("\x1B#8\x1B#6");
+        assert_eq!(term.framebuffer(), &vec![String::from("alignment_test"), String::from("line_attr(DoubleWidth)")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn designate_charset() {
+        let line_drawing = AnsiType::DesignateCharset { slot: CharsetSlot::G0, charset: Charset::DecSpecialGraphics };
+        assert_eq!(ansi_escaper::escape("\x1B(0"), (line_drawing.clone(), 3));
+        assert_eq!(ansi_escaper::escape("\x1B)B"), (AnsiType::DesignateCharset { slot: CharsetSlot::G1, charset: Charset::Ascii }, 3));
+        assert_eq!(ansi_escaper::escape("\x1B(").0, AnsiType::Incomplete);
+        assert_eq!(line_drawing.encode(), "\x1B(0");
+        assert_eq!("\x1B(0q\x1B(B".to_ansi().collect::<Vec<AnsiType>>(), vec![
+            line_drawing,
+            AnsiType::Text(String::from("q")),
+            AnsiType::DesignateCharset { slot: CharsetSlot::G0, charset: Charset::Ascii },
+        ]);
+
+        let mut term = Recorder::term();
+        term.write("\x1B)0");
+        assert_eq!(term.framebuffer(), &vec![String::from("designate_charset(G1, DecSpecialGraphics)")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn line_drawing() {
+        assert_eq!(dec_special_to_unicode(b'q'), '─');
+        assert_eq!(dec_special_to_unicode(b'x'), '│');
+        assert_eq!(dec_special_to_unicode(b'A'), 'A');
+        assert_eq!(Charset::DecSpecialGraphics.translate("lqk"), "┌─┐");
+        assert_eq!(Charset::Ascii.translate("lqk"), "lqk");
+
+        let mut term = Recorder::term();
+        term.set_translate_charsets(true);
+        term.write("\x1B(0lqk\x1B(Blqk");
+        assert_eq!(term.framebuffer(), &vec![
+            String::from("designate_charset(G0, DecSpecialGraphics)"),
+            String::from("write(┌─┐)"),
+            String::from("designate_charset(G0, Ascii)"),
+            String::from("write(lqk)"),
+        ]);
+
+        // Off by default
+        let mut term = Recorder::term();
+        term.write("\x1B(0lqk");
+        assert_eq!(term.framebuffer().last(), Some(&String::from("write(lqk)")));
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn framebuffer_mut() {
+        let mut term = Recorder::term();
+        term.write("a");
+        term.framebuffer_mut().clear();
+        term.write("b");
+        assert_eq!(term.framebuffer(), &vec![String::from("write(b)")]);
+
+        let mut term = TermStatic::new(Recorder { events: vec![] });
+        term.framebuffer_mut().push(String::from("resized"));
+        assert_eq!(term.framebuffer(), &vec![String::from("resized")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn index_sequences() {
+        assert_eq!(ansi_escaper::escape("\x1BDx"), (AnsiType::Index, 2));
+        assert_eq!(ansi_escaper::escape("\x1BM"), (AnsiType::ReverseIndex, 2));
+        assert_eq!(ansi_escaper::escape("\x1BE"), (AnsiType::NextLine, 2));
+        assert_eq!(AnsiType::from_byte(0x8D), AnsiType::ReverseIndex);
+        assert_eq!(AnsiType::NextLine.encode(), "\x1BE");
+        assert_eq!("\x1BD\x1BM\x1BE".to_ansi().collect::<Vec<AnsiType>>(), vec![AnsiType::Index, AnsiType::ReverseIndex, AnsiType::NextLine]);
+
+        let mut term = Recorder::term();
+        term.write("\x1BD\x1BM\x1BE");
+        assert_eq!(term.framebuffer(), &vec![
+            String::from("move_y(1)"),
+            String::from("move_y(-1)"),
+            String::from("goto_x(1)"),
+            String::from("move_y(1)"),
+        ]);
+
+        let mut screen = ScreenState::new(24, 80);
+        for ansi in "ab\x1BE\x1BM\x1BM\x1BD".to_ansi() {
+            screen.apply(&ansi);
+        }
+        assert_eq!(screen.position(), (2, 1));
+    }
+
+    #[test]
+    fn screen_state_tabs() {
+        let mut screen = ScreenState::new(24, 80);
+        for (col, expected) in [(0, 9), (1, 9), (5, 9), (9, 17), (78, 80)] {
+            screen.col = col;
+            screen.apply(&AnsiType::Control(ControlChar::Tab));
+            assert_eq!(screen.col, expected);
+        }
+
+        let mut screen = ScreenState::with_tab_width(24, 80, 4);
+        screen.apply(&AnsiType::Text(String::from("ab\tc")));
+        assert_eq!(screen.position(), (1, 6));
+        screen.apply(&AnsiType::RIS);
+        screen.col = usize::MAX;
+        screen.apply(&AnsiType::Text(String::from("a")));
+        screen.apply(&AnsiType::RIS);
+        screen.apply(&AnsiType::Control(ControlChar::Tab));
+        assert_eq!((screen.tab_width, screen.position()), (4, (1, 5)));
+    }
+
+    #[test]
+    fn empty_parameters() {
+        let cases = [
+            ("\x1B[;5H", CSIType::CUP(1, 5)),
+            ("\x1B[5;H", CSIType::CUP(5, 1)),
+            ("\x1B[;H", CSIType::CUP(1, 1)),
+            ("\x1B[;;H", CSIType::CUP(1, 1)),
+            ("\x1B[;3r", CSIType::DECSTBM(1, 3)),
+            ("\x1B[1;m", CSIType::SGR(SgrParams::from_iter([1, 0]))),
+            ("\x1B[;1m", CSIType::SGR(SgrParams::from_iter([0, 1]))),
+        ];
+        for (input, kind) in cases {
+            let expected = AnsiType::CSI { kind };
+            assert_eq!(ansi_escaper::escape(input), (expected.clone(), input.len()), "{:?}", input);
+            assert_eq!(input.to_ansi().parse_next(), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn escape_at_walks_string() {
+        let input = "\x1B[31mhi\x1B[0m\r\nab\x1B]0;title\x07€\x1B[2;3H\x1BDend";
+        let mut offset = 0;
+        let mut events = vec![];
+        while offset < input.len() {
+            let (ansi, length) = ansi_escaper::escape_at(input, offset);
+            assert!(length > 0, "stuck at {}", offset);
+            events.push(ansi);
+            offset += length;
+        }
+        assert_eq!(offset, input.len());
+        assert_eq!(events, input.to_ansi().collect::<Vec<AnsiType>>());
+    }
+
+    #[test]
+    fn eq_ignoring_unknown_message() {
+        let a = AnsiType::Unknown { raw: String::from("\x1B%"), reason: String::from("Unknown ansi escape char: %") };
+        let b = AnsiType::Unknown { raw: String::from("\x1B%"), reason: String::from("No idea what % is") };
+        assert_ne!(a, b);
+        assert!(a.eq_ignoring_unknown_message(&b));
+
+        let a = AnsiType::CSI { kind: CSIType::Unknown { raw: String::new(), reason: String::from("Unknown CSI command: y") } };
+        let b = AnsiType::CSI { kind: CSIType::Unknown { raw: String::new(), reason: String::from("y") } };
+        assert_ne!(a, b);
+        assert!(a.eq_ignoring_unknown_message(&b));
+        assert!(AnsiType::OSC { kind: OSCType::Unknown { raw: String::new(), reason: String::from("1") } }
+            .eq_ignoring_unknown_message(&AnsiType::OSC { kind: OSCType::Unknown { raw: String::new(), reason: String::from("2") } }));
+
+        // Everything else still has to match
+        assert!(!a.eq_ignoring_unknown_message(&AnsiType::CSI { kind: CSIType::CUU(1) }));
+        assert!(!AnsiType::sgr(&[1]).eq_ignoring_unknown_message(&AnsiType::sgr(&[2])));
+        assert!(AnsiType::sgr(&[1]).eq_ignoring_unknown_message(&AnsiType::sgr(&[1])));
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn push_pop_sgr() {
+        assert_eq!(ansi_escaper::escape("\x1B[#p"), (AnsiType::CSI { kind: CSIType::PushSgr }, 4));
+        assert_eq!(ansi_escaper::escape("\x1B[#q"), (AnsiType::CSI { kind: CSIType::PopSgr }, 4));
+        assert_eq!(ansi_escaper::escape("\x1B[#{").0, AnsiType::CSI { kind: CSIType::PushSgr });
+        assert_eq!("\x1B[#}".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::PopSgr });
+        assert_eq!(AnsiType::CSI { kind: CSIType::PushSgr }.encode(), "\x1B[#p");
+
+        let mut term = Recorder::term();
+        term.write("\x1B[#p\x1B[1m\x1B[#q");
+        assert_eq!(term.framebuffer(), &vec![String::from("push_sgr"), String::from("sgr([1])"), String::from("pop_sgr")]);
+    }
+
+    #[test]
+    fn parse_all() {
+        let mut escaper = "\x1B[31mhi\x1B[0m\x1B[1".to_ansi();
+        assert_eq!(escaper.parse_all(), vec![
+            AnsiType::sgr(&[31]),
+            AnsiType::Text(String::from("hi")),
+            AnsiType::sgr(&[0]),
+        ]);
+        escaper.new_text("m");
+        assert_eq!(escaper.parse_all(), vec![AnsiType::sgr(&[1])]);
+        assert!(escaper.parse_all().is_empty());
+    }
+
+    #[test]
+    fn sgr_leading_extended_color() {
+        let parse = |s: &str| match ansi_escaper::escape(s).0 {
+            AnsiType::CSI { kind: CSIType::SGR(params) } => parse_sgr(params.as_slice()),
+            other => panic!("not an SGR: {:?}", other),
+        };
+        assert_eq!(parse("\x1B[38;5;9m"), vec![SgrParam::Foreground(Color::Indexed(9))]);
+        assert_eq!(parse("\x1B[1;38;5;9m"), vec![SgrParam::Bold, SgrParam::Foreground(Color::Indexed(9))]);
+        assert_eq!(parse("\x1B[48;2;1;2;3;4m"), vec![SgrParam::Background(Color::Rgb(1, 2, 3)), SgrParam::Underline(UnderlineStyle::Single)]);
+    }
+
+    #[test]
+    fn sgr_default_and_bright_colors() {
+        assert_eq!(parse_sgr(&[39, 49]), vec![SgrParam::Foreground(Color::Default), SgrParam::Background(Color::Default)]);
+        assert_eq!(parse_sgr(&[91, 104]), vec![SgrParam::Foreground(Color::BrightRed), SgrParam::Background(Color::BrightBlue)]);
+        assert_eq!(parse_sgr(&[97, 100]), vec![SgrParam::Foreground(Color::BrightWhite), SgrParam::Background(Color::BrightBlack)]);
+
+        let mut style = SgrStyle::default();
+        style.apply_sgr(&[31, 44, 39]);
+        assert_eq!((style.fg, style.bg), (Color::Default, Color::Blue));
+    }
+
+    #[test]
+    fn unknown_recovery() {
+        let input = "\x1B>\x1B%\x1B[31;\x1B[31mhi\x1B[5\u{263A}m\x1B]0;a\x1B[1m\x1BP\x1Bxz";
+        let mut rest = input;
+        let mut events = vec![];
+        loop {
+            let (ansi, length) = ansi_escaper::escape(rest);
+            if ansi == AnsiType::Incomplete {
+                break;
+            }
+            assert!(length > 0, "no progress at {:?}", rest);
+            events.push(ansi);
+            rest = &rest[length..];
+        }
+        assert!(rest.is_empty());
+        let unknowns = events.iter().filter(|ansi| matches!(ansi, AnsiType::Unknown { .. })).count();
+        assert_eq!(unknowns, 6);
+        assert!(events.contains(&AnsiType::KeypadNumeric));
+        assert!(events.contains(&AnsiType::sgr(&[31])));
+        assert!(events.contains(&AnsiType::sgr(&[1])));
+        assert!(events.contains(&AnsiType::Text(String::from("hi"))));
+        assert_eq!(events.last(), Some(&AnsiType::Text(String::from("z"))));
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn delete_lines() {
+        assert_eq!(ansi_escaper::escape("\x1B[2M"), (AnsiType::CSI { kind: CSIType::DL(2) }, 4));
+        assert_eq!("\x1B[2M".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::DL(2) });
+        assert_eq!(AnsiType::CSI { kind: CSIType::DL(2) }.encode(), "\x1B[2M");
+        // Without parameters it's still a mouse report
+        let mouse = AnsiType::CSI { kind: CSIType::MouseX10 { button: 0, x: 1, y: 1 } };
+        assert_eq!(ansi_escaper::escape("\x1B[M !!"), (mouse.clone(), 6));
+        assert_eq!("\x1B[M !!".to_ansi().parse_next(), mouse);
+
+        let mut term = Recorder::term();
+        term.write("\x1B[3M");
+        assert_eq!(term.framebuffer(), &vec![String::from("delete_lines(3)")]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn resize() {
+        let mut term = Recorder::term();
+        term.resize(24, 80);
+        term.write("\x1B[1;24r");
+        term.resize(30, 100);
+        assert_eq!(term.framebuffer(), &vec![
+            String::from("resize(24, 80)"),
+            String::from("decstbm(1, 24)"),
+            String::from("resize(30, 100)"),
+            String::from("decstbm(1, 30)"),
+        ]);
+
+        // A smaller region stays as it is
+        let mut term = TermStatic::new(Recorder { events: vec![] });
+        term.resize(24, 80);
+        term.write("\x1B[2;10r");
+        term.resize(30, 100);
+        assert_eq!(term.framebuffer().last(), Some(&String::from("resize(30, 100)")));
+    }
+
+    #[test]
+    fn sgr_fonts() {
+        assert_eq!(parse_sgr(&[11, 20, 21, 10]), vec![SgrParam::Font(1), SgrParam::Fraktur, SgrParam::DoubleUnderline, SgrParam::Font(0)]);
+
+        let mut style = SgrStyle::default();
+        style.apply_sgr(&[11, 20, 21]);
+        assert_eq!((style.font, style.fraktur, style.underline), (1, true, UnderlineStyle::Double));
+    }
+
+    #[test]
+    fn styled_spans_resolve_style() {
+        let red = SgrStyle { fg: Color::Red, ..SgrStyle::default() };
+        let bold_red = SgrStyle { bold: true, ..red };
+        assert_eq!(styled_spans("\x1B[31mred\x1B[1mboldred\x1B[0mplain"), vec![
+            (red, String::from("red")),
+            (bold_red, String::from("boldred")),
+            (SgrStyle::default(), String::from("plain")),
+        ]);
+        // Sequences not changing the style don't split a span
+        assert_eq!(styled_spans("a\x1B[2Jb\nc\x1B[1m"), vec![(SgrStyle::default(), String::from("ab\nc"))]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn overflowing_parameters() {
+        let huge = "\x1B[99999999999999999999;99999999999999999999H";
+        assert_eq!(ansi_escaper::escape(huge).0, AnsiType::CSI { kind: CSIType::CUP(usize::MAX, usize::MAX) });
+        assert_eq!(huge.to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::CUP(usize::MAX, usize::MAX) });
+        assert_eq!(ansi_escaper::escape("\x1B[99999999999999999999A").0, AnsiType::CSI { kind: CSIType::CUU(usize::MAX) });
+        let mut params = SgrParams::from_iter([4]);
+        params.push_sub(usize::MAX);
+        assert_eq!(ansi_escaper::escape("\x1B[4:99999999999999999999m").0, AnsiType::CSI { kind: CSIType::SGR(params) });
+
+        let mut term = Recorder::term();
+        term.write(huge);
+        term.write("\x1B[99999999999999999999A");
+        assert_eq!(term.framebuffer().last(), Some(&format!("move_y({})", -isize::MAX)));
+
+        // Still ends up in the bottom right corner instead of the top left
+        let mut screen = ScreenState::new(24, 80);
+        screen.apply(&ansi_escaper::escape(huge).0);
+        assert_eq!(screen.position(), (24, 80));
+    }
+
+    #[test]
+    fn escaper_new_bytes() {
+        let mut escaper = AnsiEscaper::new();
+        let bytes = "aé".as_bytes();
+        escaper.new_bytes(&bytes[..2]);
+        escaper.flush();
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("a")));
+        assert_eq!(escaper.parse_next(), AnsiType::Incomplete);
+        escaper.new_bytes(&bytes[2..]);
+        escaper.flush();
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("é")));
+
+        escaper.new_bytes(b"\x1B[1m\xFF");
+        escaper.flush();
+        assert_eq!(escaper.parse_all(), vec![AnsiType::sgr(&[1]), AnsiType::Text(String::from("\u{FFFD}"))]);
+    }
+
+    #[test]
+    fn screen_state_backspace() {
+        let mut screen = ScreenState::new(24, 80);
+        screen.col = 5;
+        screen.apply(&AnsiType::Control(ControlChar::Backspace));
+        assert_eq!(screen.col, 4);
+        screen.col = 1;
+        screen.apply(&AnsiType::Control(ControlChar::Backspace));
+        assert_eq!(screen.col, 1);
+
+        // Overstrike inside a text run
+        screen.apply(&AnsiType::Text(String::from("ab\x08b")));
+        assert_eq!(screen.col, 3);
+        screen.col = 1;
+        screen.apply(&AnsiType::Text(String::from("\x08\x08a")));
+        assert_eq!(screen.col, 2);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn cursor_position_row_major() {
+        let mut term = Recorder::term();
+        term.write("\x1B[3;7H");
+        assert_eq!(term.framebuffer(), &vec![String::from("goto_x(7)"), String::from("goto_y(3)")]);
+
+        assert_eq!(ansi_escaper::escape("\x1B[3;7f").0, AnsiType::CSI { kind: CSIType::HVP(3, 7) });
+        let mut term = Recorder::term();
+        term.write("\x1B[3;7f");
+        assert_eq!(term.framebuffer(), &vec![String::from("goto_x(7)"), String::from("goto_y(3)")]);
+
+        // Implementors overriding cursor_position get the row first
+        struct Position(Vec<(usize, usize)>);
+        impl TermInterface<Vec<(usize, usize)>> for Position {
+            fn framebuffer(&self) -> &Vec<(usize, usize)> { &self.0 }
+            fn framebuffer_mut(&mut self) -> &mut Vec<(usize, usize)> { &mut self.0 }
+            fn completed_render(&mut self) {}
+            fn write(&mut self, _s: String) {}
+            fn goto_x(&mut self, _x: usize) {}
+            fn goto_y(&mut self, _y: usize) {}
+            fn move_x(&mut self, _x: isize) {}
+            fn move_y(&mut self, _y: isize) {}
+            fn cursor_position(&mut self, n: usize, m: usize) { self.0.push((n, m)) }
+            fn erase_in_display(&mut self, _n: usize) {}
+            fn erase_in_line(&mut self, _n: usize) {}
+            fn scroll_up(&mut self, _n: usize) {}
+            fn scroll_down(&mut self, _n: usize) {}
+            fn il(&mut self, _n: usize) {}
+            fn select_graphics_rendition(&mut self, _n: SgrParams) {}
+            fn decstbm(&mut self, _top: usize, _bot: usize) {}
+            fn decslrm(&mut self, _left: usize, _right: usize) {}
+            fn dectcem(&mut self, _show: bool) {}
+            fn device_status_report(&mut self) -> (usize, usize) { (1, 1) }
+            fn unknown_csi(&mut self, _s: String) {}
+            fn set_title(&mut self, _title: String) {}
+            fn unknown_osc(&mut self, _s: String) {}
+            fn unknown(&mut self, _s: String) {}
+        }
+        let mut term = TermStatic::new(Position(vec![]));
+        term.write("\x1B[3;7H");
+        assert_eq!(term.framebuffer(), &vec![(3, 7)]);
+    }
+
+    #[test]
+    fn max_text_chunk() {
+        let mut escaper = "abcdé\u{0301}fg\x1B[1mhijkl".to_ansi();
+        escaper.set_max_text_chunk(4);
+        assert_eq!(escaper.parse_all(), vec![
+            AnsiType::Text(String::from("abcd")),
+            // The accented e is 4 bytes on its own
+            AnsiType::Text(String::from("é\u{0301}")),
+            AnsiType::Text(String::from("fg")),
+            AnsiType::sgr(&[1]),
+            AnsiType::Text(String::from("hijk")),
+            AnsiType::Text(String::from("l")),
+        ]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn decsca() {
+        assert_eq!(ansi_escaper::escape("\x1B[1\"q"), (AnsiType::CSI { kind: CSIType::DECSCA(1) }, 5));
+        assert_eq!("\x1B[\"q".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::DECSCA(0) });
+        assert_eq!(AnsiType::CSI { kind: CSIType::DECSCA(1) }.encode(), "\x1B[1\"q");
+        // Unknown combinations keep the intermediate in the message
+        assert_eq!(ansi_escaper::escape("\x1B[1\"z").0, AnsiType::CSI { kind: CSIType::Unknown { raw: String::from("\x1B[1\"z"), reason: String::from("Unknown CSI command: \"z") } });
+
+        let mut term = Recorder::term();
+        term.write("\x1B[1\"q\x1B[2\"q");
+        assert_eq!(term.framebuffer(), &vec![String::from("set_char_protection(true)"), String::from("set_char_protection(false)")]);
+    }
+
+    #[test]
+    fn coalesce_text() {
+        let events = vec![AnsiType::Text("ab".into()), AnsiType::Text("cd".into()), AnsiType::Control(ControlChar::Bell), AnsiType::Text("e".into())];
+        let merged = CoalesceText::new(events.into_iter()).collect::<Vec<_>>();
+        assert_eq!(merged, vec![AnsiType::Text("abcd".into()), AnsiType::Control(ControlChar::Bell), AnsiType::Text("e".into())]);
+
+        let mut escaper = "abcdef\x1B[1mghijkl".to_ansi();
+        escaper.set_max_text_chunk(2);
+        let merged = escaper.coalesced().collect::<Vec<_>>();
+        assert_eq!(merged, vec![
+            AnsiType::Text("abcdef".into()),
+            AnsiType::sgr(&[1]),
+            AnsiType::Text("ghijkl".into()),
+        ]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn device_status() {
+        let status = AnsiType::CSI { kind: CSIType::DSR(DsrRequest::Status) };
+        assert_eq!(ansi_escaper::escape("\x1B[5n"), (status.clone(), 4));
+        assert_eq!("\x1B[5n".to_ansi().parse_next(), status);
+        assert_eq!(ansi_escaper::dsr_ok_response(), "\x1B[0n");
+
+        let extended = AnsiType::CSI { kind: CSIType::DSR(DsrRequest::ExtendedCursorPosition) };
+        assert_eq!(ansi_escaper::escape("\x1B[?6n"), (extended.clone(), 5));
+        assert_eq!(extended.encode(), "\x1B[?6n");
+        assert_eq!(ansi_escaper::escape("\x1B[6n").0, AnsiType::CSI { kind: CSIType::DSR(DsrRequest::CursorPosition) });
+
+        let mut term = Recorder::term();
+        term.write("\x1B[5n\x1B[?6n");
+        assert_eq!(term.framebuffer(), &vec![
+            String::from("device_status(Status)"),
+            String::from("device_status(ExtendedCursorPosition)"),
+        ]);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn clone_static_term() {
+        let mut term = TermStatic::new(Recorder { events: vec![] });
+        term.write("a\x1B[");
+        let mut copy = term.try_clone().unwrap();
+        term.write("1m");
+        copy.write("2J");
+        assert_eq!(term.framebuffer(), &vec![String::from("write(a)"), String::from("sgr([1])")]);
+        assert_eq!(copy.framebuffer(), &vec![String::from("write(a)"), String::from("erase_in_display(2)")]);
+
+        // A copy would lose the filter, so there is none while one is set
+        term.set_event_filter(|ansi| !matches!(ansi, AnsiType::OSC { .. }));
+        assert!(term.try_clone().is_none());
+        term.write("\x1B]0;title\x07");
+        assert_eq!(term.framebuffer().len(), 2);
+        term.clear_event_filter();
+        let mut copy = term.try_clone().unwrap();
+        copy.write("\x1B]0;title\x07");
+        assert_eq!(copy.framebuffer().last(), Some(&String::from("set_title(title)")));
+    }
+
+    #[test]
+    fn clone_escaper() {
+        let mut escaper = "ab\x1B[3".to_ansi();
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("ab")));
+        let mut fork = escaper.clone();
+        fork.new_text("1mc");
+        fork.flush();
+        assert_eq!(fork.parse_all(), vec![
+            AnsiType::sgr(&[31]),
+            AnsiType::Text(String::from("c")),
+        ]);
+        assert_eq!(escaper.parse_next(), AnsiType::Incomplete);
+        escaper.new_text("2m");
+        assert_eq!(escaper.parse_all(), vec![AnsiType::sgr(&[32])]);
+    }
+
+    #[test]
+    fn sgr_attribute_off() {
+        let mut style = SgrStyle::default();
+        style.apply_sgr(&[1, 22]);
+        assert!(!style.bold && !style.faint);
+        style.apply_sgr(&[2, 22]);
+        assert!(!style.bold && !style.faint);
+
+        style.apply_sgr(&[3, 4, 5, 7, 8, 9]);
+        style.apply_sgr(&[23, 25, 27, 28]);
+        assert_eq!((style.italic, style.blink, style.reverse, style.conceal), (false, false, false, false));
+        // Everything not switched off is kept
+        assert_eq!((style.underline, style.crossed_out), (UnderlineStyle::Single, true));
+        style.apply_sgr(&[24, 29]);
+        assert_eq!(style, SgrStyle::default());
+    }
+
+    #[test]
+    fn into_string() {
+        let sgr = AnsiType::sgr(&[0, 31]);
+        assert_eq!(String::from(&sgr), "\x1B[0;31m");
+        let s: String = sgr.into();
+        assert_eq!(s, "\x1B[0;31m");
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn notification() {
+        let iterm = AnsiType::OSC { kind: OSCType::Notification { title: None, body: String::from("done; 3 files") } };
+        assert_eq!(ansi_escaper::escape("\x1B]9;done; 3 files\x07"), (iterm.clone(), 18));
+        assert_eq!(iterm.encode(), "\x1B]9;done; 3 files\x07");
+
+        let urxvt = AnsiType::OSC { kind: OSCType::Notification { title: Some(String::from("make")), body: String::from("finished") } };
+        assert_eq!("\x1B]777;notify;make;finished\x07".to_ansi().parse_next(), urxvt);
+        assert_eq!(urxvt.encode(), "\x1B]777;notify;make;finished\x07");
+        assert!(matches!(ansi_escaper::escape("\x1B]777;other\x07").0, AnsiType::OSC { kind: OSCType::Unknown { .. } }));
+
+        let mut term = Recorder::term();
+        term.write("\x1B]777;notify;make;finished\x07");
+        assert_eq!(term.framebuffer(), &vec![String::from("notify(Some(\"make\"), finished)")]);
+    }
+
+    #[test]
+    fn working_directory() {
+        let cwd = AnsiType::OSC { kind: OSCType::WorkingDirectory { uri: String::from("file://localhost/home/user") } };
+        assert_eq!(ansi_escaper::escape("\x1B]7;file://localhost/home/user\x07"), (cwd.clone(), 31));
+        assert_eq!("\x1B]7;file://localhost/home/user\x1B\\".to_ansi().parse_next(), cwd);
+        assert_eq!(cwd.encode(), "\x1B]7;file://localhost/home/user\x07");
+        // Not decoded
+        let spaced = AnsiType::OSC { kind: OSCType::WorkingDirectory { uri: String::from("file://host/a%20b") } };
+        assert_eq!(ansi_escaper::escape("\x1B]7;file://host/a%20b\x07").0, spaced);
+    }
+
+    #[cfg(feature = "smallvec")]
+    #[test]
+    fn sgr_params_inline() {
+        let AnsiType::CSI { kind: CSIType::SGR(params) } = ansi_escaper::escape("\x1B[1;4;31m").0 else { panic!() };
+        assert_eq!(params.as_slice(), [1, 4, 31]);
+        assert!(!params.spilled());
+    }
+
+    #[test]
+    fn escape_short_inputs() {
+        assert_eq!(ansi_escaper::escape(""), (AnsiType::Incomplete, 0));
+        assert_eq!(ansi_escaper::escape("a"), (AnsiType::Text(String::from("a")), 1));
+        assert_eq!(ansi_escaper::escape("ab"), (AnsiType::Text(String::from("ab")), 2));
+        assert_eq!(ansi_escaper::escape("\x1B"), (AnsiType::Incomplete, 0));
+        // Non-escape input always makes progress
+        for s in ["é\u{301}", "\r\n", "\x07", "\u{FEFF}", "a\x1B"] {
+            assert!(ansi_escaper::escape(s).1 > 0, "{:?}", s);
+        }
+        // A doubled ESC only skips the first one
+        let (ansi, len) = ansi_escaper::escape("\x1B\x1B[m");
+        assert!(matches!(ansi, AnsiType::Unknown { .. }));
+        assert_eq!(len, 1);
+        assert_eq!(ansi_escaper::escape("\x1B[m"), (AnsiType::sgr(&[0]), 3));
+    }
+
+    #[test]
+    fn pending_bytes() {
+        let mut escaper = "ab\x1B[12;".to_ansi();
+        assert_eq!(escaper.pending_bytes(), 7);
+        assert_eq!(escaper.parse_next(), AnsiType::Text(String::from("ab")));
+        assert_eq!(escaper.parse_next(), AnsiType::Incomplete);
+        assert_eq!(escaper.pending_bytes(), 5);
+        escaper.new_bytes(&"€".as_bytes()[..2]);
+        assert_eq!(escaper.pending_bytes(), 7);
+        escaper.reset();
+        assert_eq!(escaper.pending_bytes(), 0);
+    }
+
+    #[test]
+    fn screen_origin_mode() {
+        let mut screen = ScreenState::new(24, 80);
+        for ansi in "\x1B[3;10r\x1B[?6h\x1B[1;1H".to_ansi() {
+            screen.apply(&ansi);
+        }
+        assert!(screen.origin_mode);
+        assert_eq!(screen.position(), (3, 1));
+        // Stays within the region
+        screen.apply(&AnsiType::cursor_to(20, 5));
+        assert_eq!(screen.position(), (10, 5));
+
+        screen.apply(&ansi_escaper::escape("\x1B[?6l").0);
+        assert_eq!(screen.position(), (1, 1));
+        screen.apply(&AnsiType::cursor_to(20, 5));
+        assert_eq!(screen.position(), (20, 5));
+        assert_eq!(ansi_escaper::escape("\x1B[?6h").0, AnsiType::CSI { kind: CSIType::DECSET(vec![DecPrivateMode::Origin]) });
+    }
+
+    #[test]
+    fn encode_bytes() {
+        let text = AnsiType::Text(String::from("café"));
+        assert_eq!(text.encode_bytes(), b"caf\xC3\xA9");
+        assert_eq!(ansi_escaper::escape_bytes(&text.encode_bytes()), (text, 5));
+
+        let sgr = AnsiType::sgr(&[1]);
+        assert_eq!(ansi_escaper::escape_bytes(&sgr.encode_bytes()), (sgr, 4));
+        // Cut off in the middle of the é
+        assert_eq!(ansi_escaper::escape_bytes(b"\xC3"), (AnsiType::Incomplete, 0));
+        assert_eq!(ansi_escaper::escape_bytes(b"caf\xC3"), (AnsiType::Text(String::from("caf")), 3));
+
+        // Long input is decoded bit by bit, which mustn't matter where the bits end
+        for prefix in 60..70 {
+            let mut bytes = b"a".repeat(prefix);
+            bytes.extend_from_slice(b"\xC3\xA9\xFF\x9B31m");
+            let text = format!("{}é\u{FFFD}", "a".repeat(prefix));
+            assert_eq!(ansi_escaper::escape_bytes(&bytes), (AnsiType::Text(text), prefix + 3));
+            assert_eq!(ansi_escaper::escape_bytes(&bytes[prefix + 3..]), (AnsiType::sgr(&[31]), 4));
+        }
+        let long_title = format!("\x1B]0;{}\x07", "é".repeat(500));
+        let (ansi, len) = ansi_escaper::escape_bytes(long_title.as_bytes());
+        assert_eq!((ansi, len), (AnsiType::set_title(&"é".repeat(500)), long_title.len()));
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn write_outcome() {
+        let mut term = Recorder::term();
+        assert_eq!(term.write("\x1B[31m"), WriteOutcome { events: 1, buffered_bytes: 0 });
+        assert_eq!(term.write("a\x1B[3"), WriteOutcome { events: 1, buffered_bytes: 3 });
+        assert_eq!(term.write("2"), WriteOutcome { events: 0, buffered_bytes: 4 });
+        assert_eq!(term.write("m"), WriteOutcome { events: 1, buffered_bytes: 0 });
+
+        term.set_event_filter(|ansi| !matches!(ansi, AnsiType::Text(_)));
+        assert_eq!(term.write("b\x07"), WriteOutcome { events: 1, buffered_bytes: 0 });
+    }
+
+    #[test]
+    fn scroll_region_reset() {
+        assert_eq!(ansi_escaper::escape("\x1B[r"), (AnsiType::CSI { kind: CSIType::DECSTBM(0, 0) }, 3));
+        assert_eq!("\x1B[r".to_ansi().parse_next(), AnsiType::CSI { kind: CSIType::DECSTBM(0, 0) });
+        assert_eq!(ansi_escaper::escape("\x1B[2;23r").0, AnsiType::CSI { kind: CSIType::DECSTBM(2, 23) });
+        assert_eq!(ansi_escaper::escape("\x1B[5r").0, AnsiType::CSI { kind: CSIType::DECSTBM(5, 0) });
+
+        let mut screen = ScreenState::new(24, 80);
+        screen.apply(&ansi_escaper::escape("\x1B[2;23r").0);
+        assert_eq!(screen.scroll_region, Some((2, 23)));
+        screen.apply(&ansi_escaper::escape("\x1B[r").0);
+        assert_eq!(screen.scroll_region, None);
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn term_scroll_region_reset() {
+        let mut term = Recorder::term();
+        term.write("\x1B[r");
+        term.resize(30, 80);
+        term.write("\x1B[2;23r\x1B[r");
+        assert_eq!(term.framebuffer(), &vec![
+            String::from("decstbm(1, 0)"),
+            String::from("resize(30, 80)"),
+            String::from("decstbm(2, 23)"),
+            String::from("decstbm(1, 30)"),
+        ]);
+    }
+
+    #[test]
+    fn cursor_style_default() {
+        for input in ["\x1B[0 q", "\x1B[ q"] {
+            let AnsiType::CSI { kind: CSIType::DECSCUSR(n) } = ansi_escaper::escape(input).0 else { panic!("{:?}", input) };
+            assert_eq!(CursorStyle::from(n), CursorStyle::Default, "{:?}", input);
+        }
+        assert_eq!(CursorStyle::from(1), CursorStyle::BlinkingBlock);
+    }
+
+    #[test]
+    fn escaper_does_not_stall() {
+        // Each of these once kept `AnsiEscaper` waiting for more input, while `escape` skipped them
+        let cases = [
+            "\x1Bx",
+            "\u{9C}",
+            "\x1B\\",
+            "\x1BPab\x1B\\",
+            "\u{90}ab\u{9C}",
+            "\x1B_a\x07b\x1B\\",
+            "\x1BNa",
+            "\x1BOa",
+            "\x1B]\u{9B}\x07",
+        ];
+        for input in cases {
+            let s = format!("{}z", input);
+            let mut escaper = s.to_ansi();
+            let parsed = escaper.parse_all();
+            assert_eq!(parsed.len(), 2, "{:?} {:?}", input, parsed);
+            assert_eq!(parsed.last(), Some(&AnsiType::Text(String::from("z"))), "{:?}", input);
+            assert_eq!(escaper.pending_bytes(), 0, "{:?}", input);
+        }
+        // Another sequence inside a string ends it, and is parsed on its own
+        let parsed = "\x1BPa\x1B[1m".to_ansi().parse_all();
+        assert!(matches!(parsed[0], AnsiType::Unknown { .. }));
+        assert_eq!(parsed[1], AnsiType::sgr(&[1]));
+    }
+
+    #[test]
+    fn escape_c1_inside_string() {
+        // Only ST ends a string, other C1 characters are part of it
+        let (ansi, len) = ansi_escaper::escape("\x1B]\u{9B}\x07z");
+        assert!(matches!(ansi, AnsiType::OSC { .. }));
+        assert_eq!(len, 5);
+        assert_eq!(ansi_escaper::escape("\x1BPa\u{9B}b\x1B\\z"), (AnsiType::DCS, 8));
+        assert_eq!(ansi_escaper::escape("\u{90}a\u{9D}\u{9C}z"), (AnsiType::DCS, 7));
+    }
+
+    #[test]
+    fn escape_bytes_invariants() {
+        // Same checks as the fuzz target in `fuzz/`, on a fixed set of inputs
+        let alphabet = b"\x1B[]P?;019:<Mm#( !$\"qprsnH\x07\x9B\x9D\x9C\x90\xC3\xA9\xFF\\a\r\n\x08\xE2\x82\xAC5cD\tx";
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        for _ in 0..20_000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let len = (seed % 16) as usize;
+            let bytes = (0..len)
+                .map(|i| alphabet[(seed >> (4 + i * 3)) as usize % alphabet.len()])
+                .collect::<Vec<u8>>();
+            let mut rest = &bytes[..];
+            while !rest.is_empty() {
+                let (ansi, len) = ansi_escaper::escape_bytes(rest);
+                if ansi == AnsiType::Incomplete {
+                    assert_eq!(len, 0, "{:?}", bytes);
+                    break;
+                }
+                assert!(len > 0 && len <= rest.len(), "{:?} {:?} {}", bytes, ansi, len);
+                rest = &rest[len..];
+            }
+        }
+    }
+
+    #[cfg(feature = "term")]
+    #[test]
+    fn term_truncated_csi() {
+        let mut term = Recorder::term();
+        term.write("\x1B[3");
+        term.write("\x1B[31mhi\x1B[3;\x01mok");
+        assert_eq!(term.framebuffer(), &vec![
+            format!("unknown({})", AnsiError::Interrupted),
+            String::from("sgr([31])"),
+            String::from("write(hi)"),
+            format!("unknown({})", AnsiError::IllegalParameterByte { byte: 1, offset: 4 }),
+            String::from("write(ok)"),
+        ]);
+    }
+
+    #[test]
+    fn escaper_large_buffer() {
+        // 2 MB of sequences, copying the buffer for every one of them would never finish
+        let input = "\x1B[1m".repeat(1 << 19);
+        let mut escaper = input.to_ansi();
+        let mut count = 0;
+        while escaper.peek() != AnsiType::Incomplete {
+            assert_eq!(escaper.parse_next(), AnsiType::sgr(&[1]));
+            count += 1;
+        }
+        assert_eq!((count, escaper.pending_bytes()), (1 << 19, 0));
+    }
+
+    #[test]
+    fn mouse_x10_round_trip() {
+        for n in [1, 95, 96, 223] {
+            let ansi = AnsiType::CSI { kind: CSIType::MouseX10 { button: 0, x: n, y: n } };
+            let encoded = ansi.encode();
+            assert_eq!(encoded.len(), ansi.byte_len());
+            assert_eq!(ansi_escaper::escape(&encoded), (ansi.clone(), encoded.len()), "{}", n);
+            assert_eq!(encoded.to_ansi().parse_next(), ansi, "{}", n);
+        }
+        // Past the last character the protocol has, the value is clamped instead of turning into NUL
+        let clamped = AnsiType::CSI { kind: CSIType::MouseX10 { button: 255, x: 224, y: 1000 } };
+        let expected = AnsiType::CSI { kind: CSIType::MouseX10 { button: 223, x: 223, y: 223 } };
+        assert_eq!(clamped.encode(), expected.encode());
+        assert_eq!(clamped.byte_len(), expected.encode().len());
+        assert_eq!(ansi_escaper::escape(clamped.encode()).0, expected);
     }
 }
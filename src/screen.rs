@@ -0,0 +1,217 @@
+use unicode_segmentation::UnicodeSegmentation;
+use crate::ansi_escaper::{AnsiType, CSIType, ControlChar, DecPrivateMode};
+#[cfg(feature = "unicode-width")]
+use crate::ansi_escaper::text_width;
+use crate::sgr::SgrStyle;
+
+/// Tracks the cursor position and the current SGR attributes of a stream, for when
+/// implementing the whole `TermInterface` is overkill.
+///
+/// `row` and `col` start at 1,1 (top left) and are kept within `rows` and `cols`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScreenState {
+    pub row: usize,
+    pub col: usize,
+    pub rows: usize,
+    pub cols: usize,
+    /// Attributes set by the SGR sequences so far.
+    pub style: SgrStyle,
+    /// Whether text wraps to the next line at the right margin (DECAWM, mode 7).
+    pub wrap: bool,
+    /// Distance between tab stops, 8 by default.
+    pub tab_width: usize,
+    /// Top and bottom margin set by DECSTBM, `None` for the whole screen.
+    pub scroll_region: Option<(usize, usize)>,
+    /// Whether CUP, HVP and VPA count rows from the top margin (DECOM, mode 6).
+    pub origin_mode: bool,
+    /// Set after writing to the last column, the wrap happens when the next character arrives.
+    pending_wrap: bool,
+}
+
+impl Default for ScreenState {
+    /// A 24 by 80 screen, the VT100 default.
+    fn default() -> Self {
+        Self::new(24, 80)
+    }
+}
+
+impl ScreenState {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            row: 1,
+            col: 1,
+            rows: rows.max(1),
+            cols: cols.max(1),
+            style: SgrStyle::default(),
+            wrap: true,
+            tab_width: 8,
+            scroll_region: None,
+            origin_mode: false,
+            pending_wrap: false,
+        }
+    }
+
+    /// Returns the cursor position as (row, column).
+    pub fn position(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    /// Same as `new`, with tab stops every `tab_width` columns.
+    pub fn with_tab_width(rows: usize, cols: usize, tab_width: usize) -> Self {
+        Self { tab_width, ..Self::new(rows, cols) }
+    }
+
+    /// Updates the state with a parsed sequence.
+    pub fn apply(&mut self, ansi: &AnsiType) {
+        if let AnsiType::Text(s) = ansi {
+            for gr in s.graphemes(true) {
+                if gr == "\t" {
+                    self.tab();
+                } else if gr == "\x08" {
+                    // Used for overstriking, e.g. `a\x08a` for a bold `a` in man pages
+                    self.backspace();
+                } else {
+                    // The parser already split off escapes and controls, no need to strip them again
+                    #[cfg(feature = "unicode-width")]
+                    self.put(text_width(gr));
+                    #[cfg(not(feature = "unicode-width"))]
+                    self.put(1);
+                }
+            }
+            return;
+        }
+        // Only moving the cursor cancels a pending wrap, anything else (attributes, modes, charsets,
+        // titles) keeps it. DECSTBM, RIS, tabs and backspaces cancel it on their own.
+        let moves = match ansi {
+            AnsiType::Control(ControlChar::LineFeed | ControlChar::CarriageReturn)
+            | AnsiType::Index | AnsiType::ReverseIndex | AnsiType::NextLine | AnsiType::DecAlignmentTest => true,
+            AnsiType::CSI { kind } => matches!(kind, CSIType::CUU(_) | CSIType::CUD(_) | CSIType::CUF(_) | CSIType::CUB(_)
+                | CSIType::CNL(_) | CSIType::CPL(_) | CSIType::CHA(_) | CSIType::CVA(_) | CSIType::CUP(..) | CSIType::HVP(..)),
+            _ => false,
+        };
+        if moves {
+            self.pending_wrap = false;
+        }
+        match ansi {
+            AnsiType::Control(ControlChar::Backspace) => self.backspace(),
+            AnsiType::Control(ControlChar::Tab) => self.tab(),
+            AnsiType::Control(ControlChar::LineFeed) | AnsiType::Index => self.row += 1,
+            AnsiType::ReverseIndex => self.row = self.row.saturating_sub(1),
+            AnsiType::NextLine => (self.row, self.col) = (self.row + 1, 1),
+            AnsiType::Control(ControlChar::CarriageReturn) => self.col = 1,
+            AnsiType::CSI { kind } => match kind {
+                CSIType::CUU(n) => self.row = self.row.saturating_sub(*n),
+                CSIType::CUD(n) => self.row = self.row.saturating_add(*n),
+                CSIType::CUF(n) => self.col = self.col.saturating_add(*n),
+                CSIType::CUB(n) => self.col = self.col.saturating_sub(*n),
+                CSIType::CNL(n) => {
+                    self.row = self.row.saturating_add(*n);
+                    self.col = 1;
+                }
+                CSIType::CPL(n) => {
+                    self.row = self.row.saturating_sub(*n);
+                    self.col = 1;
+                }
+                CSIType::CHA(n) => self.col = *n,
+                CSIType::CVA(n) => self.row = self.origin_row(*n),
+                CSIType::CUP(row, col) | CSIType::HVP(row, col) => {
+                    self.row = self.origin_row(*row);
+                    self.col = *col;
+                }
+                CSIType::DECSTBM(top, bottom) => {
+                    let top = (*top).max(1);
+                    let bottom = if *bottom == 0 { self.rows } else { (*bottom).min(self.rows) };
+                    // An empty or inverted region resets it to the whole screen
+                    self.scroll_region = (top < bottom && (top, bottom) != (1, self.rows)).then_some((top, bottom));
+                    self.home();
+                }
+                CSIType::SGR(params) => {
+                    if kind.is_sgr_reset() {
+                        self.style = SgrStyle::default();
+                    } else {
+                        self.style.apply_params(params);
+                    }
+                }
+                CSIType::DECSTR => {
+                    self.style = SgrStyle::default();
+                    self.scroll_region = None;
+                    self.origin_mode = false;
+                }
+                CSIType::DECSET(modes) | CSIType::DECRST(modes) => {
+                    let set = matches!(kind, CSIType::DECSET(_));
+                    for mode in modes {
+                        match mode {
+                            DecPrivateMode::AutoWrap => {
+                                self.wrap = set;
+                                self.pending_wrap &= set;
+                            }
+                            DecPrivateMode::Origin => {
+                                self.origin_mode = set;
+                                self.home();
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            },
+            // The size and tab width are set up by the caller, not by the stream
+            AnsiType::RIS => *self = Self::with_tab_width(self.rows, self.cols, self.tab_width),
+            AnsiType::DecAlignmentTest => (self.row, self.col) = (1, 1),
+            _ => {}
+        }
+        // Like a real terminal, moving past an edge stops at it instead of scrolling
+        self.row = self.row.clamp(1, self.rows);
+        self.col = self.col.clamp(1, self.cols);
+    }
+
+    /// Converts a row from CUP, HVP or VPA into a screen row, in origin mode it counts from the
+    /// top margin and stays within the scroll region.
+    fn origin_row(&self, row: usize) -> usize {
+        match (self.origin_mode, self.scroll_region) {
+            (true, Some((top, bottom))) => top.saturating_add(row.saturating_sub(1)).min(bottom),
+            _ => row,
+        }
+    }
+
+    /// Moves to the top left, which is the top margin in origin mode.
+    fn home(&mut self) {
+        self.pending_wrap = false;
+        self.row = self.origin_row(1);
+        self.col = 1;
+    }
+
+    /// Moves one column left, stopping at the first one.
+    fn backspace(&mut self) {
+        self.pending_wrap = false;
+        self.col = self.col.saturating_sub(1).max(1);
+    }
+
+    /// Moves to the next tab stop, or the last column if there is none left.
+    fn tab(&mut self) {
+        let width = self.tab_width.max(1);
+        self.pending_wrap = false;
+        // `col` is public and may have been set to 0
+        self.col = ((self.col.saturating_sub(1) / width + 1) * width + 1).min(self.cols);
+    }
+
+    /// Advances the cursor over a character `width` columns wide.
+    fn put(&mut self, width: usize) {
+        if width == 0 {
+            return;
+        }
+        if self.pending_wrap || (self.wrap && self.col > 1 && self.col.saturating_add(width - 1) > self.cols) {
+            // Either the last column was already written to, or a wide character doesn't fit
+            self.pending_wrap = false;
+            self.col = 1;
+            self.row = self.row.saturating_add(1).min(self.rows);
+        }
+        if self.col.saturating_add(width) > self.cols {
+            // Stay on the last column, without autowrap the next character overwrites it
+            self.col = self.cols;
+            self.pending_wrap = self.wrap;
+        } else {
+            self.col += width;
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::ansi_escaper::{parse_param, AnsiType, CSIType, ToAnsi};
+
+#[cfg(not(feature = "smallvec"))]
+type Codes = Vec<usize>;
+#[cfg(feature = "smallvec")]
+type Codes = smallvec::SmallVec<[usize; 4]>;
+
+/// The parameters of a `CSIType::SGR`. With the `smallvec` feature up to 4 of them are stored
+/// inline, which covers most sequences without allocating. The type and its methods are the same
+/// either way.
+///
+/// Colon sub-parameters that have no flat equivalent (the `3` in `4:3`) are kept as codes of their
+/// own, marked as belonging to the code before them.
+#[derive(Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SgrParams {
+    codes: Codes,
+    /// Indexes into `codes` that follow the previous code after a `:`, in ascending order
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    subs: Vec<usize>,
+}
+
+impl SgrParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, code: usize) {
+        self.codes.push(code);
+    }
+
+    /// Appends `code` as a colon sub-parameter of the last code, `push(4); push_sub(3)` is `4:3`.
+    pub fn push_sub(&mut self, code: usize) {
+        self.subs.push(self.codes.len());
+        self.codes.push(code);
+    }
+
+    /// Whether the code at `index` is a sub-parameter of the one before it.
+    pub fn is_sub(&self, index: usize) -> bool {
+        self.subs.binary_search(&index).is_ok()
+    }
+
+    /// All codes, sub-parameters included. Use `parse` to decode them, `parse_sgr` on this slice
+    /// can't tell `4:3` from `4;3`.
+    pub fn as_slice(&self) -> &[usize] {
+        &self.codes
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, usize> {
+        self.codes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    /// Decodes the parameters into attributes, see `parse_sgr`.
+    pub fn parse(&self) -> Vec<SgrParam> {
+        decode(&self.codes, |i| self.is_sub(i))
+    }
+
+    /// Whether the parameters didn't fit inline and had to be moved to the heap.
+    #[cfg(feature = "smallvec")]
+    pub fn spilled(&self) -> bool {
+        self.codes.spilled()
+    }
+}
+
+impl core::fmt::Debug for SgrParams {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        struct Group<'a>(&'a [usize]);
+        impl core::fmt::Debug for Group<'_> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                for (i, code) in self.0.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(":")?;
+                    }
+                    write!(f, "{}", code)?;
+                }
+                Ok(())
+            }
+        }
+        let mut list = f.debug_list();
+        let mut start = 0;
+        for end in 1..=self.codes.len() {
+            if end == self.codes.len() || !self.is_sub(end) {
+                list.entry(&Group(&self.codes[start..end]));
+                start = end;
+            }
+        }
+        list.finish()
+    }
+}
+
+impl From<&[usize]> for SgrParams {
+    fn from(codes: &[usize]) -> Self {
+        codes.iter().copied().collect()
+    }
+}
+
+impl From<Vec<usize>> for SgrParams {
+    fn from(codes: Vec<usize>) -> Self {
+        codes.into_iter().collect()
+    }
+}
+
+impl FromIterator<usize> for SgrParams {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        Self { codes: iter.into_iter().collect(), subs: Vec::new() }
+    }
+}
+
+impl Extend<usize> for SgrParams {
+    fn extend<I: IntoIterator<Item = usize>>(&mut self, iter: I) {
+        self.codes.extend(iter);
+    }
+}
+
+impl<'a> IntoIterator for &'a SgrParams {
+    type Item = &'a usize;
+    type IntoIter = core::slice::Iter<'a, usize>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.codes.iter()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Color {
+    /// Whatever the terminal uses when no color is set
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    /// 256-color palette index (`38;5;n`)
+    Indexed(u8),
+    /// Truecolor (`38;2;r;g;b`)
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Maps `0..=7` to the eight standard named colors and `8..=15` to their bright versions,
+    /// same as the first 16 entries of the 256-color palette.
+    pub fn from_index(n: usize) -> Option<Color> {
+        match n {
+            0 => Some(Color::Black),
+            1 => Some(Color::Red),
+            2 => Some(Color::Green),
+            3 => Some(Color::Yellow),
+            4 => Some(Color::Blue),
+            5 => Some(Color::Magenta),
+            6 => Some(Color::Cyan),
+            7 => Some(Color::White),
+            8 => Some(Color::BrightBlack),
+            9 => Some(Color::BrightRed),
+            10 => Some(Color::BrightGreen),
+            11 => Some(Color::BrightYellow),
+            12 => Some(Color::BrightBlue),
+            13 => Some(Color::BrightMagenta),
+            14 => Some(Color::BrightCyan),
+            15 => Some(Color::BrightWhite),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnderlineStyle {
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+/// A single decoded SGR attribute.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SgrParam {
+    Reset,
+    Bold,
+    Faint,
+    Italic,
+    Underline(UnderlineStyle),
+    Blink,
+    Reverse,
+    Conceal,
+    CrossedOut,
+    /// Primary (`10`, font 0) or alternative font `11` - `19`
+    Font(u8),
+    /// `20`, hardly supported anywhere
+    Fraktur,
+    /// `21`. ECMA-48 makes this double underline, but some terminals (older Linux consoles
+    /// among others) treat it as "bold off" instead
+    DoubleUnderline,
+    /// `22`, neither bold nor faint
+    NormalIntensity,
+    /// `23`
+    NotItalic,
+    /// `25`
+    NotBlinking,
+    /// `27`
+    NotReversed,
+    /// `28`
+    Reveal,
+    /// `29`
+    NotCrossedOut,
+    Foreground(Color),
+    Background(Color),
+    /// Separate underline color (`58`)
+    UnderlineColor(Color),
+    /// Back to underlining in the foreground color (`59`)
+    ResetUnderlineColor,
+    /// Code this decoder doesn't know about, or a malformed extended color.
+    Unknown(usize),
+}
+
+/// The style text ends up with after applying a run of SGR codes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SgrStyle {
+    pub fg: Color,
+    pub bg: Color,
+    /// `Color::Default` underlines in the foreground color.
+    pub underline_color: Color,
+    pub bold: bool,
+    pub faint: bool,
+    pub italic: bool,
+    pub underline: UnderlineStyle,
+    pub blink: bool,
+    pub reverse: bool,
+    pub conceal: bool,
+    pub crossed_out: bool,
+    /// 0 is the primary font.
+    pub font: u8,
+    pub fraktur: bool,
+}
+
+impl Default for SgrStyle {
+    /// Default colors, no attributes set.
+    fn default() -> Self {
+        Self {
+            fg: Color::Default,
+            bg: Color::Default,
+            underline_color: Color::Default,
+            bold: false,
+            faint: false,
+            italic: false,
+            underline: UnderlineStyle::None,
+            blink: false,
+            reverse: false,
+            conceal: false,
+            crossed_out: false,
+            font: 0,
+            fraktur: false,
+        }
+    }
+}
+
+impl SgrStyle {
+    /// Applies a single decoded attribute, `Unknown` ones are ignored.
+    pub fn apply_param(&mut self, param: &SgrParam) {
+        match *param {
+            SgrParam::Reset => *self = Self::default(),
+            SgrParam::Bold => self.bold = true,
+            SgrParam::Faint => self.faint = true,
+            SgrParam::Italic => self.italic = true,
+            SgrParam::Underline(style) => self.underline = style,
+            SgrParam::Blink => self.blink = true,
+            SgrParam::Reverse => self.reverse = true,
+            SgrParam::Conceal => self.conceal = true,
+            SgrParam::CrossedOut => self.crossed_out = true,
+            SgrParam::Font(font) => self.font = font,
+            SgrParam::Fraktur => self.fraktur = true,
+            SgrParam::DoubleUnderline => self.underline = UnderlineStyle::Double,
+            SgrParam::NormalIntensity => {
+                self.bold = false;
+                self.faint = false;
+            }
+            SgrParam::NotItalic => self.italic = false,
+            SgrParam::NotBlinking => self.blink = false,
+            SgrParam::NotReversed => self.reverse = false,
+            SgrParam::Reveal => self.conceal = false,
+            SgrParam::NotCrossedOut => self.crossed_out = false,
+            SgrParam::Foreground(color) => self.fg = color,
+            SgrParam::Background(color) => self.bg = color,
+            SgrParam::UnderlineColor(color) => self.underline_color = color,
+            SgrParam::ResetUnderlineColor => self.underline_color = Color::Default,
+            SgrParam::Unknown(_) => {}
+        }
+    }
+
+    /// Applies plain SGR codes, without sub-parameters.
+    pub fn apply_sgr(&mut self, codes: &[usize]) {
+        for param in parse_sgr(codes) {
+            self.apply_param(&param);
+        }
+    }
+
+    /// Applies the parameters of a `CSIType::SGR`.
+    pub fn apply_params(&mut self, params: &SgrParams) {
+        for param in params.parse() {
+            self.apply_param(&param);
+        }
+    }
+}
+
+/// Splits `s` into runs of text with the style they are shown in. Control characters are kept in
+/// the text, every other sequence is dropped.
+pub fn styled_spans<S: AsRef<str>>(s: S) -> Vec<(SgrStyle, String)> {
+    let mut spans: Vec<(SgrStyle, String)> = Vec::new();
+    let mut style = SgrStyle::default();
+    for ansi in s.as_ref().to_ansi() {
+        let text = match ansi {
+            AnsiType::CSI { kind: CSIType::SGR(params) } => {
+                style.apply_params(&params);
+                continue;
+            }
+            AnsiType::Text(text) => text,
+            AnsiType::Control(c) => String::from(c.as_char()),
+            _ => continue,
+        };
+        match spans.last_mut() {
+            Some((last, run)) if *last == style => run.push_str(&text),
+            _ => spans.push((style, text)),
+        }
+    }
+    spans
+}
+
+/// Decodes plain SGR codes into attributes. Use `SgrParams::parse` for the parameters of a
+/// `CSIType::SGR`, which may carry sub-parameters.
+pub fn parse_sgr(params: &[usize]) -> Vec<SgrParam> {
+    decode(params, |_| false)
+}
+
+fn decode(params: &[usize], is_sub: impl Fn(usize) -> bool) -> Vec<SgrParam> {
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while i < params.len() {
+        let code = params[i];
+        i += 1;
+        let param = match code {
+            4 if i < params.len() && is_sub(i) => {
+                let style = match params[i] {
+                    0 => SgrParam::Underline(UnderlineStyle::None),
+                    1 => SgrParam::Underline(UnderlineStyle::Single),
+                    2 => SgrParam::Underline(UnderlineStyle::Double),
+                    3 => SgrParam::Underline(UnderlineStyle::Curly),
+                    4 => SgrParam::Underline(UnderlineStyle::Dotted),
+                    5 => SgrParam::Underline(UnderlineStyle::Dashed),
+                    _ => SgrParam::Unknown(code),
+                };
+                while i < params.len() && is_sub(i) {
+                    i += 1;
+                }
+                style
+            }
+            0 => SgrParam::Reset,
+            1 => SgrParam::Bold,
+            2 => SgrParam::Faint,
+            3 => SgrParam::Italic,
+            4 => SgrParam::Underline(UnderlineStyle::Single),
+            5 => SgrParam::Blink,
+            7 => SgrParam::Reverse,
+            8 => SgrParam::Conceal,
+            9 => SgrParam::CrossedOut,
+            10..=19 => SgrParam::Font((code - 10) as u8),
+            20 => SgrParam::Fraktur,
+            21 => SgrParam::DoubleUnderline,
+            22 => SgrParam::NormalIntensity,
+            23 => SgrParam::NotItalic,
+            24 => SgrParam::Underline(UnderlineStyle::None),
+            25 => SgrParam::NotBlinking,
+            27 => SgrParam::NotReversed,
+            28 => SgrParam::Reveal,
+            29 => SgrParam::NotCrossedOut,
+            30..=37 => SgrParam::Foreground(Color::from_index(code - 30).unwrap()),
+            39 => SgrParam::Foreground(Color::Default),
+            40..=47 => SgrParam::Background(Color::from_index(code - 40).unwrap()),
+            49 => SgrParam::Background(Color::Default),
+            90..=97 => SgrParam::Foreground(Color::from_index(code - 90 + 8).unwrap()),
+            100..=107 => SgrParam::Background(Color::from_index(code - 100 + 8).unwrap()),
+            38 | 48 | 58 => {
+                match parse_extended_color(&params[i..]) {
+                    Some((color, used)) => {
+                        i += used;
+                        match code {
+                            38 => SgrParam::Foreground(color),
+                            48 => SgrParam::Background(color),
+                            _ => SgrParam::UnderlineColor(color),
+                        }
+                    }
+                    None => {
+                        // Skip the rest, there is no way to tell where the color was supposed to end
+                        i = params.len();
+                        SgrParam::Unknown(code)
+                    }
+                }
+            }
+            59 => SgrParam::ResetUnderlineColor,
+            _ => SgrParam::Unknown(code),
+        };
+        ret.push(param);
+    }
+    ret
+}
+
+/// Parses the `5;n` or `2;r;g;b` tail of an extended color, returning the color and how many
+/// parameters it used.
+fn parse_extended_color(params: &[usize]) -> Option<(Color, usize)> {
+    match params.first() {
+        Some(5) => {
+            let n = u8::try_from(*params.get(1)?).ok()?;
+            Some((Color::Indexed(n), 2))
+        }
+        Some(2) => {
+            let r = u8::try_from(*params.get(1)?).ok()?;
+            let g = u8::try_from(*params.get(2)?).ok()?;
+            let b = u8::try_from(*params.get(3)?).ok()?;
+            Some((Color::Rgb(r, g, b), 4))
+        }
+        _ => None,
+    }
+}
+
+/// Pushes one `;`-separated SGR parameter onto `out`, normalizing ITU T.416 style colon
+/// color sub-parameters (`38:2::r:g:b`, `38:5:n`) into the flat form. The underline style in `4:3`
+/// has no flat form and stays a sub-parameter.
+pub(crate) fn push_sgr_param(arg: &str, out: &mut SgrParams) {
+    if !arg.contains(':') {
+        out.push(parse_param(arg).unwrap_or(0));
+        return;
+    }
+    // Empty sub-parameters (the `::`) are kept as `None`
+    let subs = arg.split(':').map(parse_param).collect::<Vec<Option<usize>>>();
+    match subs[0] {
+        Some(head @ (38 | 48 | 58)) => {
+            out.push(head);
+            match subs.get(1) {
+                Some(Some(2)) => {
+                    // `38:2:<color space>:r:g:b`, older producers leave out the color space
+                    let rgb = if subs.len() >= 6 { &subs[3..6] } else { &subs[2..] };
+                    out.push(2);
+                    out.extend(rgb.iter().map(|c| c.unwrap_or(0)));
+                }
+                Some(Some(5)) => {
+                    out.push(5);
+                    out.push(subs.get(2).copied().flatten().unwrap_or(0));
+                }
+                _ => {}
+            }
+        }
+        Some(4) => {
+            out.push(4);
+            if let Some(Some(style)) = subs.get(1) {
+                out.push_sub(*style);
+            }
+        }
+        head => out.push(head.unwrap_or(0)),
+    }
+}
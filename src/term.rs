@@ -1,16 +1,19 @@
 // TODO: Extract this into a new crate
 use alloc::boxed::Box;
+use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
-use std::println;
-use crate::ansi_escaper::{AnsiEscaper, AnsiType, CSIType, OSCType};
+use core::marker::PhantomData;
+use crate::ansi_escaper::{AnsiEscaper, AnsiType, Charset, CharsetSlot, CSIType, ControlChar, CursorStyle, DecLineAttr, DecPrivateMode, DsrRequest, EraseRegion, OSCType};
+use crate::sgr::SgrParams;
 
 extern crate unicode_segmentation;
 
 pub trait TermInterface<T> {
     /// Returns a reference to the imaginary framebuffer of the terminal.
-    // TODO: Is this good enough? Is there a need for a mutable reference?
     fn framebuffer(&self) -> &T;
+    /// Returns a mutable reference to the framebuffer, e.g. for resizing it.
+    fn framebuffer_mut(&mut self) -> &mut T;
     /// Called when renderer has rendered so you can check for dirty bits.
     // TODO: Remove and force rendering side to buffer the framebuffer maybe?
     fn completed_render(&mut self);
@@ -44,41 +47,41 @@ pub trait TermInterface<T> {
     // CSI
     /// Moves the cursor up *n* (default `1`) cells. If the cursor is already at the edge of the screen, this has no effect.
     fn cursor_up(&mut self, n: usize) {
-        self.goto_rel(0, -(n as isize));
+        self.goto_rel(0, -distance(n));
     }
     /// Moves the cursor down *n* (default `1`) cells. If the cursor is already at the edge of the screen, this has no effect.
     fn cursor_down(&mut self, n: usize) {
-        self.goto_rel(0, n as isize);
+        self.goto_rel(0, distance(n));
     }
     /// Moves the cursor forward *n* (default `1`) cells. If the cursor is already at the edge of the screen, this has no effect.
     fn cursor_forward(&mut self, n: usize) {
-        self.goto_rel(n as isize, 0);
+        self.goto_rel(distance(n), 0);
     }
     /// Moves the cursor back *n* (default `1`) cells. If the cursor is already at the edge of the screen, this has no effect.
     fn cursor_back(&mut self, n: usize) {
-        self.goto_rel(-(n as isize), 0);
+        self.goto_rel(-distance(n), 0);
     }
     /// Moves the cursor to the beginning of the line *n* (default `1`) lines down.
     fn cursor_next_line(&mut self, n: usize) {
         self.goto_x(1);
-        self.move_y(n as isize);
+        self.move_y(distance(n));
     }
     /// Moves the cursor to the beginning of the line *n* (default `1`) lines up.
     fn cursor_prev_line(&mut self, n: usize) {
         self.goto_x(1);
-        self.move_y(-(n as isize));
+        self.move_y(-distance(n));
     }
     /// Moves the cursor to column *n* (default `1`).
     fn cursor_horizontal_absolute(&mut self, n: usize) {
-        self.goto_y(n);
+        self.goto_x(n);
     }
     /// Moves the cursor to row *n* (default `1`).
     fn cursor_vertical_absolute(&mut self, n: usize) {
-        self.goto_x(n);
+        self.goto_y(n);
     }
     /// Moves the cursor to row *n*, column *m* (default `1`/`1`).
     fn cursor_position(&mut self, n: usize, m: usize) {
-        self.goto(n, m);
+        self.goto(m, n);
     }
     /// Clears part of the screen.
     ///
@@ -91,6 +94,14 @@ pub trait TermInterface<T> {
     ///
     /// Note: Cursor position does not change.
     fn erase_in_display(&mut self, n: usize);
+    /// Same as `erase_in_display`, with `n` decoded. `ED 3` goes to `clear_scrollback` instead.
+    fn erase_display_region(&mut self, region: EraseRegion) {
+        self.erase_in_display(region.value());
+    }
+    /// Clears the entire screen and the scrollback buffer (`ED 3`).
+    fn clear_scrollback(&mut self) {
+        self.erase_in_display(3);
+    }
     /// Clears part of the line.
     ///
     /// different `n`-values:
@@ -101,25 +112,70 @@ pub trait TermInterface<T> {
     ///
     /// Note: Cursor position does not change.
     fn erase_in_line(&mut self, n: usize);
+    /// Same as `erase_in_line`, with `n` decoded. Never gets `AllWithScrollback`.
+    fn erase_line_region(&mut self, region: EraseRegion) {
+        self.erase_in_line(region.value());
+    }
     /// Scroll up page by `n` lines.
     fn scroll_up(&mut self, n: usize);
     /// Scroll down page by `n` lines.
     fn scroll_down(&mut self, n: usize);
+    /// Scroll the page left by `n` columns (SL).
+    fn scroll_left(&mut self, _n: usize) {}
+    /// Scroll the page right by `n` columns (SR).
+    fn scroll_right(&mut self, _n: usize) {}
     /// Moves the current line by `n` lines, clearing the current line in the process.
     // TODO: Rename function to more be intuitive.
     fn il(&mut self, n: usize);
+    /// Deletes `n` lines starting at the cursor line, the lines below move up (DL).
+    fn delete_lines(&mut self, _n: usize) {}
     /// Moves the cursor to row *n*, column *m* (default `1`/`1`).
     fn horizontal_vertical_position(&mut self, n: usize, m: usize) {
-        self.goto(n, m);
+        self.goto(m, n);
     }
     /// Sets colors and style of the characters following.
-    fn select_graphics_rendition(&mut self, n: Vec<usize>);
+    fn select_graphics_rendition(&mut self, n: SgrParams);
     /// Set top and bottom margins. Moves the cursor to column 1, line 1 of the page.
+    /// `bot` is 0 for the last row if the screen size wasn't given with `Term::resize`.
     fn decstbm(&mut self, top: usize, bot: usize);
+    /// The screen is now `rows` by `cols`, called by `Term::resize`.
+    fn resize(&mut self, _rows: usize, _cols: usize) {}
     /// Set left and right margins. Moves the cursor to column 1, line 1 of the page.
     fn decslrm(&mut self, left: usize, right: usize);
+    /// Saves the cursor position (SCOSC).
+    fn save_cursor(&mut self) {}
+    /// Moves the cursor back to the position saved by `save_cursor` (SCORC).
+    fn restore_cursor(&mut self) {}
+    /// Saves the current SGR attributes on a stack (XTPUSHSGR).
+    fn push_sgr(&mut self) {}
+    /// Restores the SGR attributes saved by the last `push_sgr` (XTPOPSGR). Does nothing if
+    /// the stack is empty.
+    fn pop_sgr(&mut self) {}
     /// Shows or hides cursor based on the `show` argument.
     fn dectcem(&mut self, show: bool);
+    /// Turns wrapping at the right margin (DECAWM) on or off.
+    fn set_autowrap(&mut self, _wrap: bool) {}
+    /// Turns origin mode (DECOM) on or off, making cursor addressing relative to the scroll region.
+    fn set_origin_mode(&mut self, _origin: bool) {}
+    /// Turns cursor blinking on or off.
+    fn set_cursor_blink(&mut self, _blink: bool) {}
+    /// Sets the cursor shape (DECSCUSR).
+    fn set_cursor_style(&mut self, _style: CursorStyle) {}
+    /// Turns protection from selective erase on or off for the characters following (DECSCA).
+    fn set_char_protection(&mut self, _protected: bool) {}
+    /// Window manipulation (XTWINOPS), see `CSIType::WindowOp` for the meaning of `params`.
+    fn window_op(&mut self, _params: Vec<usize>) {}
+    /// Soft terminal reset (DECSTR), restores modes and attributes without clearing the screen.
+    fn soft_reset(&mut self) {}
+    /// A mouse button was pressed or released at column `x`, row `y`. `button` is the raw button
+    /// code, with the modifier bits still set.
+    fn mouse_event(&mut self, _button: usize, _x: usize, _y: usize, _pressed: bool) {}
+    /// The application asks whether DEC private mode `mode` is set (DECRQM), the reply is built
+    /// with `ansi_escaper::decrpm`.
+    fn request_mode(&mut self, _mode: usize) {}
+    /// The application asks for the terminal status or the cursor position (DSR), a status
+    /// reply is built with `ansi_escaper::dsr_ok_response`.
+    fn device_status(&mut self, _request: DsrRequest) {}
     /// Should return a tuple of the current row and column as (row, column).
     fn device_status_report(&mut self) -> (usize, usize);
     /// Unknown csi code.
@@ -128,24 +184,158 @@ pub trait TermInterface<T> {
     // OSI
     /// Sets the title of the terminal window.
     fn set_title(&mut self, title: String);
+    /// Sets the clipboard `selection` (e.g. `c`) to the base64 encoded `data`, or asks for its
+    /// content if `data` is `?`.
+    fn set_clipboard(&mut self, _selection: String, _data: String) {}
+    /// Following text links to `uri` until the next call with an empty `uri`.
+    fn hyperlink(&mut self, _params: String, _uri: String) {}
+    /// The shell changed its directory to `uri`, a `file://` URI.
+    fn set_working_directory(&mut self, _uri: String) {}
+    /// Shows a desktop notification, `title` is only sent by the urxvt form.
+    fn notify(&mut self, _title: Option<String>, _body: String) {}
     /// Unknown osc code.
     fn unknown_osc(&mut self, s: String);
 
     // Other
+    /// Full terminal reset (RIS). Should clear the screen, reset all SGR attributes, move the
+    /// cursor home and restore the default modes. See `soft_reset` for the reset keeping the screen.
+    fn reset(&mut self) {}
+    /// Rings the bell (BEL).
+    fn bell(&mut self) {}
+    /// Moves the cursor to the beginning of the line (CR).
+    fn carriage_return(&mut self) {
+        self.goto_x(1);
+    }
+    /// Moves the cursor one line down (LF).
+    fn line_feed(&mut self) {
+        self.move_y(1);
+    }
+    /// Moves the cursor one line down (IND). At the bottom margin the page should scroll up instead.
+    fn index(&mut self) {
+        self.move_y(1);
+    }
+    /// Moves the cursor one line up (RI). At the top margin the page should scroll down instead.
+    fn reverse_index(&mut self) {
+        self.move_y(-1);
+    }
+    /// Moves the cursor to the beginning of the next line (NEL), scrolling like `index`.
+    fn next_line(&mut self) {
+        self.goto_x(1);
+        self.index();
+    }
+    /// Switches the keypad between application (`true`) and numeric (`false`) mode.
+    fn set_keypad_mode(&mut self, _application: bool) {}
+    /// Selects the character set used by `slot`. Only G0 is used until the application shifts to
+    /// another slot.
+    fn designate_charset(&mut self, _slot: CharsetSlot, _charset: Charset) {}
+    /// Screen alignment test (DECALN). Should fill the screen with `E` and move the cursor home.
+    fn alignment_test(&mut self) {}
+    /// Changes the size of the line the cursor is on (DECDHL, DECSWL, DECDWL).
+    fn line_attr(&mut self, _attr: DecLineAttr) {}
+
     /// Unknown ANSI code.
     fn unknown(&mut self, s: String);
 }
 
+/// Converts a move count to the signed distance `move_x`/`move_y` take, saturating at `isize::MAX`.
+fn distance(n: usize) -> isize {
+    n.min(isize::MAX as usize) as isize
+}
+
 pub struct Term<T> {
     ti: Box<dyn TermInterface<T>>,
-    escaper: AnsiEscaper,
+    state: TermState,
 }
 
 impl<T> Term<T> {
     pub const fn new(ti: Box<dyn TermInterface<T>>) -> Self {
         Self {
             ti,
-            escaper: AnsiEscaper::new()
+            state: TermState::new(),
+        }
+    }
+
+    /// Returns a reference to the imaginary framebuffer of the terminal.
+    pub fn framebuffer(&self) -> &T {
+        self.ti.framebuffer()
+    }
+
+    /// Returns a mutable reference to the imaginary framebuffer of the terminal.
+    pub fn framebuffer_mut(&mut self) -> &mut T {
+        self.ti.framebuffer_mut()
+    }
+
+    pub fn completed_render(&mut self) {
+        self.ti.completed_render();
+    }
+
+    /// Sets whether the interface gets 1 based (the default, top left is 1,1) or 0 based
+    /// coordinates for CUP, HVP, CHA and CVA. Values above 1 are treated as 1.
+    pub fn set_coordinate_base(&mut self, base: u8) {
+        self.state.coordinate_base = base.min(1);
+    }
+
+    /// Calls `filter` with every parsed event before dispatching it to the interface. Events
+    /// it returns false for are skipped, e.g. to drop all title changes.
+    pub fn set_event_filter<F: FnMut(&AnsiType) -> bool + 'static>(&mut self, filter: F) {
+        self.state.event_filter = Some(Box::new(filter));
+    }
+
+    /// Removes the filter set with `set_event_filter`, every event is dispatched again.
+    pub fn clear_event_filter(&mut self) {
+        self.state.event_filter = None;
+    }
+
+    /// Tells the interface the screen is now `rows` by `cols`. A scroll region covering the whole
+    /// old screen is grown or shrunk to cover the whole new one.
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        self.state.resize(&mut *self.ti, rows, cols);
+    }
+
+    /// Sets whether text written while G0 holds the DEC special graphics set is translated to
+    /// the box drawing characters it stands for before reaching `write`. Off by default.
+    pub fn set_translate_charsets(&mut self, translate: bool) {
+        self.state.translate_charsets = translate;
+    }
+
+    /// Same as `write`, but takes raw bytes. A multibyte character split across two calls is
+    /// buffered until it is complete, invalid bytes are written as U+FFFD.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> WriteOutcome {
+        self.state.write_bytes(&mut *self.ti, bytes)
+    }
+
+    /// Parses `s` and calls the interface for every event, a sequence cut off at the end is kept
+    /// until the next call completes it. Text is written right away, so a combining character at
+    /// the start of the next call reaches `write` on its own.
+    pub fn write<S: AsRef<str>>(&mut self, s: S) -> WriteOutcome {
+        self.state.write(&mut *self.ti, s)
+    }
+}
+
+/// What a `write` call did.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WriteOutcome {
+    /// Events passed to the interface, not counting the ones the event filter dropped.
+    pub events: usize,
+    /// Bytes kept for the next call, e.g. a sequence still missing its final byte. 0 means
+    /// everything written so far has reached the interface.
+    pub buffered_bytes: usize,
+}
+
+/// Same as `Term`, but owns a concrete `TermInterface` instead of a boxed one.
+/// Works without heap allocating the interface and dispatches statically.
+pub struct TermStatic<I: TermInterface<T>, T> {
+    ti: I,
+    state: TermState,
+    _framebuffer: PhantomData<T>,
+}
+
+impl<I: TermInterface<T>, T> TermStatic<I, T> {
+    pub const fn new(ti: I) -> Self {
+        Self {
+            ti,
+            state: TermState::new(),
+            _framebuffer: PhantomData,
         }
     }
 
@@ -154,64 +344,284 @@ impl<T> Term<T> {
         self.ti.framebuffer()
     }
 
+    /// Returns a mutable reference to the imaginary framebuffer of the terminal.
+    pub fn framebuffer_mut(&mut self) -> &mut T {
+        self.ti.framebuffer_mut()
+    }
+
     pub fn completed_render(&mut self) {
         self.ti.completed_render();
     }
 
-    // TODO: what does this do?
-    pub fn write<S: AsRef<str>>(&mut self, s: S) {
+    /// Sets whether the interface gets 1 based (the default, top left is 1,1) or 0 based
+    /// coordinates for CUP, HVP, CHA and CVA. Values above 1 are treated as 1.
+    pub fn set_coordinate_base(&mut self, base: u8) {
+        self.state.coordinate_base = base.min(1);
+    }
+
+    /// Calls `filter` with every parsed event before dispatching it to the interface. Events
+    /// it returns false for are skipped, e.g. to drop all title changes.
+    pub fn set_event_filter<F: FnMut(&AnsiType) -> bool + 'static>(&mut self, filter: F) {
+        self.state.event_filter = Some(Box::new(filter));
+    }
+
+    /// Removes the filter set with `set_event_filter`, every event is dispatched again.
+    pub fn clear_event_filter(&mut self) {
+        self.state.event_filter = None;
+    }
+
+    /// Tells the interface the screen is now `rows` by `cols`. A scroll region covering the whole
+    /// old screen is grown or shrunk to cover the whole new one.
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        self.state.resize(&mut self.ti, rows, cols);
+    }
+
+    /// Sets whether text written while G0 holds the DEC special graphics set is translated to
+    /// the box drawing characters it stands for before reaching `write`. Off by default.
+    pub fn set_translate_charsets(&mut self, translate: bool) {
+        self.state.translate_charsets = translate;
+    }
+
+    /// Same as `write`, but takes raw bytes. A multibyte character split across two calls is
+    /// buffered until it is complete, invalid bytes are written as U+FFFD.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> WriteOutcome {
+        self.state.write_bytes(&mut self.ti, bytes)
+    }
+
+    pub fn write<S: AsRef<str>>(&mut self, s: S) -> WriteOutcome {
+        self.state.write(&mut self.ti, s)
+    }
+}
+
+impl<I: TermInterface<T> + Clone, T> TermStatic<I, T> {
+    /// Copies the interface and the parser state, including anything still buffered. Returns
+    /// `None` while an event filter is set: the closure can't be copied, and a copy without it
+    /// would let through the events it is there to drop.
+    pub fn try_clone(&self) -> Option<Self> {
+        Some(Self {
+            ti: self.ti.clone(),
+            state: self.state.try_clone()?,
+            _framebuffer: PhantomData,
+        })
+    }
+}
+
+type EventFilter = Box<dyn FnMut(&AnsiType) -> bool>;
+
+/// Parser state shared by `Term` and `TermStatic`.
+struct TermState {
+    escaper: AnsiEscaper,
+    /// 1 if the interface takes coordinates starting at the top left at 1,1, 0 for 0,0.
+    coordinate_base: u8,
+    /// Sees every event before it is dispatched, returning false skips it.
+    event_filter: Option<EventFilter>,
+    translate_charsets: bool,
+    /// Character set designated to G0, tracked even when not translating.
+    g0: Charset,
+    /// Rows and columns from the last `resize`.
+    size: Option<(usize, usize)>,
+    /// Top and bottom margin from the last DECSTBM.
+    scroll_region: Option<(usize, usize)>,
+}
+
+/// Logs every parsed event, `Incomplete` at debug and `Unknown` at warn level.
+#[cfg(feature = "log")]
+fn log_event(ansi: &AnsiType) {
+    match ansi {
+        AnsiType::Incomplete => log::debug!("Incomplete sequence, waiting for more input"),
+        AnsiType::Unknown { .. } => log::warn!("{}", ansi),
+        _ => log::trace!("{}", ansi),
+    }
+}
+
+impl TermState {
+    /// `None` if there is an event filter, see `TermStatic::try_clone`.
+    fn try_clone(&self) -> Option<Self> {
+        if self.event_filter.is_some() {
+            return None;
+        }
+        Some(Self {
+            escaper: self.escaper.clone(),
+            coordinate_base: self.coordinate_base,
+            event_filter: None,
+            translate_charsets: self.translate_charsets,
+            g0: self.g0,
+            size: self.size,
+            scroll_region: self.scroll_region,
+        })
+    }
+
+
+    const fn new() -> Self {
+        Self {
+            escaper: AnsiEscaper::new(),
+            coordinate_base: 1,
+            event_filter: None,
+            translate_charsets: false,
+            g0: Charset::Ascii,
+            size: None,
+            scroll_region: None,
+        }
+    }
+
+    fn resize<T, I: TermInterface<T> + ?Sized>(&mut self, ti: &mut I, rows: usize, cols: usize) {
+        let full_screen = matches!((self.scroll_region, self.size), (Some(region), Some((old_rows, _))) if region == (1, old_rows));
+        self.size = Some((rows, cols));
+        ti.resize(rows, cols);
+        if full_screen {
+            self.scroll_region = Some((1, rows));
+            ti.decstbm(1, rows);
+        }
+    }
+
+    /// Converts a parsed 1 based coordinate into the interface's base.
+    fn coordinate(&self, n: usize) -> usize {
+        n.saturating_add(self.coordinate_base as usize).saturating_sub(1)
+    }
+
+    fn write_bytes<T, I: TermInterface<T> + ?Sized>(&mut self, ti: &mut I, bytes: &[u8]) -> WriteOutcome {
+        self.escaper.new_bytes(bytes);
+        self.write(ti, "")
+    }
+
+    fn write<T, I: TermInterface<T> + ?Sized, S: AsRef<str>>(&mut self, ti: &mut I, s: S) -> WriteOutcome {
         self.escaper.new_text(s);
+        // Text is shown right away instead of waiting for combining characters that may follow
+        self.escaper.flush();
+        let mut events = 0;
         loop {
             let ansi = self.escaper.parse_next();
+            #[cfg(feature = "log")]
+            log_event(&ansi);
             if ansi != AnsiType::Incomplete {
-                println!("ANSI: {}", ansi);
+                if let Some(filter) = &mut self.event_filter {
+                    if !filter(&ansi) {
+                        continue;
+                    }
+                }
+                events += 1;
             }
             match ansi {
-                AnsiType::Text(str) => self.ti.write(str),
+                AnsiType::Text(str) if self.translate_charsets => ti.write(self.g0.translate(&str)),
+                AnsiType::Text(str) => ti.write(str),
+                AnsiType::Control(ControlChar::Bell) => ti.bell(),
+                AnsiType::Control(ControlChar::CarriageReturn) => ti.carriage_return(),
+                AnsiType::Control(ControlChar::LineFeed) => ti.line_feed(),
+                AnsiType::Control(c) => ti.write(String::from(c.as_char())),
                 AnsiType::SS2 => {}
                 AnsiType::SS3 => {}
                 AnsiType::DCS => {}
                 AnsiType::CSI { kind } => {
                     match kind {
-                        CSIType::CUU(n) => self.ti.cursor_up(n),
-                        CSIType::CUD(n) => self.ti.cursor_down(n),
-                        CSIType::CUF(n) => self.ti.cursor_forward(n),
-                        CSIType::CUB(n) => self.ti.cursor_back(n),
-                        CSIType::CNL(n) => self.ti.cursor_next_line(n),
-                        CSIType::CPL(n) => self.ti.cursor_prev_line(n),
-                        CSIType::CHA(n) => self.ti.cursor_horizontal_absolute(n),
-                        CSIType::CVA(n) => self.ti.cursor_vertical_absolute(n),
-                        CSIType::CUP(n, m) => self.ti.cursor_position(m, n),
-                        CSIType::ED(n) => self.ti.erase_in_display(n),
-                        CSIType::EL(n) => self.ti.erase_in_line(n),
-                        CSIType::SU(n) => self.ti.scroll_up(n),
-                        CSIType::SD(n) => self.ti.scroll_down(n),
-                        CSIType::IL(n) => self.ti.il(n),
-                        CSIType::HVP(n, m) => self.ti.horizontal_vertical_position(n, m),
-                        CSIType::SGR(n) => self.ti.select_graphics_rendition(n),
-                        CSIType::DECSTBM(top, bot) => self.ti.decstbm(top, bot),
-                        CSIType::DECSLRM(top, bot) => self.ti.decslrm(top, bot),
-                        CSIType::DECTCEM(show) => self.ti.dectcem(show),
-                        CSIType::Unknown(s) => self.ti.unknown_csi(s),
+                        CSIType::CUU(n) => ti.cursor_up(n),
+                        CSIType::CUD(n) => ti.cursor_down(n),
+                        CSIType::CUF(n) => ti.cursor_forward(n),
+                        CSIType::CUB(n) => ti.cursor_back(n),
+                        CSIType::CNL(n) => ti.cursor_next_line(n),
+                        CSIType::CPL(n) => ti.cursor_prev_line(n),
+                        CSIType::CHA(n) => ti.cursor_horizontal_absolute(self.coordinate(n)),
+                        CSIType::CVA(n) => ti.cursor_vertical_absolute(self.coordinate(n)),
+                        CSIType::CUP(n, m) => ti.cursor_position(self.coordinate(n), self.coordinate(m)),
+                        CSIType::ED(n) => match EraseRegion::from(n) {
+                            EraseRegion::AllWithScrollback => ti.clear_scrollback(),
+                            region => ti.erase_display_region(region),
+                        },
+                        CSIType::EL(n) => match EraseRegion::from(n) {
+                            // Only the whole display has a scrollback buffer
+                            EraseRegion::AllWithScrollback => ti.erase_line_region(EraseRegion::ToEnd),
+                            region => ti.erase_line_region(region),
+                        },
+                        CSIType::SU(n) => ti.scroll_up(n),
+                        CSIType::SD(n) => ti.scroll_down(n),
+                        CSIType::SL(n) => ti.scroll_left(n),
+                        CSIType::SR(n) => ti.scroll_right(n),
+                        CSIType::IL(n) => ti.il(n),
+                        CSIType::DL(n) => ti.delete_lines(n),
+                        CSIType::HVP(n, m) => ti.horizontal_vertical_position(self.coordinate(n), self.coordinate(m)),
+                        CSIType::SGR(n) => ti.select_graphics_rendition(n),
+                        CSIType::DECSTBM(top, bot) => {
+                            // 0 stands for the default, the first / last row
+                            let top = top.max(1);
+                            let bot = match (bot, self.size) {
+                                (0, Some((rows, _))) => rows,
+                                _ => bot,
+                            };
+                            self.scroll_region = (bot != 0).then_some((top, bot));
+                            ti.decstbm(top, bot)
+                        }
+                        CSIType::DECSLRM(top, bot) => ti.decslrm(top, bot),
+                        CSIType::SCOSC => ti.save_cursor(),
+                        CSIType::SCORC => ti.restore_cursor(),
+                        CSIType::WindowOp(params) => ti.window_op(params),
+                        CSIType::DECSTR => ti.soft_reset(),
+                        CSIType::PushSgr => ti.push_sgr(),
+                        CSIType::PopSgr => ti.pop_sgr(),
+                        CSIType::DECSCUSR(style) => ti.set_cursor_style(CursorStyle::from(style)),
+                        CSIType::DECSCA(n) => ti.set_char_protection(n == 1),
+                        CSIType::MouseSgr { button, x, y, pressed } => ti.mouse_event(button, x, y, pressed),
+                        CSIType::DecRequestMode(mode) => ti.request_mode(mode),
+                        CSIType::DSR(request) => ti.device_status(request),
+                        // The low two bits are the button, 3 means released
+                        CSIType::MouseX10 { button, x, y } => ti.mouse_event(button as usize, x, y, button & 3 != 3),
+                        CSIType::DECTCEM(show) => ti.dectcem(show),
+                        CSIType::DECSET(modes) => set_private_modes(ti, modes, true),
+                        CSIType::DECRST(modes) => set_private_modes(ti, modes, false),
+                        CSIType::Unknown { reason, .. } => ti.unknown_csi(reason),
                     }
                 }
                 AnsiType::ST => {}
                 AnsiType::OSC { kind } => {
                     match kind {
-                        OSCType::WindowTitle(title) => self.ti.set_title(title),
-                        OSCType::Unknown(s) => self.ti.unknown_osc(s),
+                        OSCType::WindowTitle(title) => ti.set_title(title),
+                        OSCType::Clipboard { selection, data } => ti.set_clipboard(selection, data),
+                        OSCType::Hyperlink { params, uri } => ti.hyperlink(params, uri),
+                        OSCType::WorkingDirectory { uri } => ti.set_working_directory(uri),
+                        OSCType::Notification { title, body } => ti.notify(title, body),
+                        OSCType::Unknown { reason, .. } => ti.unknown_osc(reason),
                     }
                 }
-                AnsiType::RIS => {}
+                AnsiType::RIS => {
+                    self.g0 = Charset::Ascii;
+                    self.scroll_region = None;
+                    ti.reset()
+                }
+                AnsiType::Index => ti.index(),
+                AnsiType::ReverseIndex => ti.reverse_index(),
+                AnsiType::NextLine => ti.next_line(),
+                AnsiType::KeypadApplication => ti.set_keypad_mode(true),
+                AnsiType::KeypadNumeric => ti.set_keypad_mode(false),
                 AnsiType::SOS => {}
                 AnsiType::PM => {}
                 AnsiType::APC => {}
                 AnsiType::Incomplete => {
                     break;
                 }
-                AnsiType::Unknown(str) => self.ti.unknown(str),
+                AnsiType::Unknown { reason, .. } => ti.unknown(reason),
                 AnsiType::SETCHARSET => {}
+                AnsiType::DesignateCharset { slot, charset } => {
+                    if slot == CharsetSlot::G0 {
+                        self.g0 = charset;
+                    }
+                    ti.designate_charset(slot, charset)
+                }
+                AnsiType::DecAlignmentTest => ti.alignment_test(),
+                AnsiType::DecLineAttr(attr) => ti.line_attr(attr),
             }
         }
+        WriteOutcome { events, buffered_bytes: self.escaper.pending_bytes() }
     }
-}
\ No newline at end of file
+}
+
+fn set_private_modes<T, I: TermInterface<T> + ?Sized>(ti: &mut I, modes: Vec<DecPrivateMode>, set: bool) {
+    for mode in modes {
+        match mode {
+            DecPrivateMode::Origin => ti.set_origin_mode(set),
+            DecPrivateMode::AutoWrap => ti.set_autowrap(set),
+            DecPrivateMode::CursorBlink => ti.set_cursor_blink(set),
+            DecPrivateMode::CursorVisible => ti.dectcem(set),
+            DecPrivateMode::Unknown(n) => {
+                ti.unknown_csi(format!("Unknown Private CSI command: {}{}", n, if set {"h"} else {"l"}))
+            }
+        }
+    }
+}